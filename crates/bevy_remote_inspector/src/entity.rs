@@ -91,7 +91,7 @@ impl TrackedData {
                         continue;
                     };
 
-                    if !ticks.is_changed(world.last_change_tick(), this_run) {
+                    if !ticks.is_changed(self.last_tick, this_run) {
                         continue;
                     }
 
@@ -210,5 +210,7 @@ impl TrackedData {
                 });
             }
         }
+
+        self.last_tick = this_run;
     }
 }
@@ -2,18 +2,24 @@ mod command;
 mod component;
 mod entity;
 mod schedule;
+mod session;
 mod type_registry;
 
 use bevy::{
     app::PluginGroupBuilder,
-    ecs::{component::ComponentId, entity::EntityHashMap},
+    core::FrameCount,
+    ecs::{
+        component::{ComponentId, Tick},
+        entity::EntityHashMap,
+    },
     prelude::*,
     remote::{error_codes, BrpError, BrpResult},
-    utils::{HashMap, HashSet},
+    utils::{Duration, HashMap, HashSet},
 };
 use bevy_remote_stream::{
-    websocket::RemoteStreamWebSocketPlugin, OnDataHandlerInput, RemoteStreamHandlers,
-    RemoteStreamPlugin, StreamClientId, StreamHandlerInputRef, StreamMethods,
+    websocket::{generate_token, RemoteStreamWebSocketPlugin},
+    OnDataHandlerInput, RemoteStreamHandlers, RemoteStreamPlugin, StreamClientId, StreamDelivery,
+    StreamHandlerInputRef, StreamMethods,
 };
 use command::Command;
 use component::InspectorComponentInfo;
@@ -21,6 +27,7 @@ use entity::EntityMutation;
 use schedule::{ScheduleInfo, SchedulesPlugin};
 use serde::Serialize;
 use serde_json::Value;
+use session::{expire_sessions, ResumeParams, SuspendedSessions};
 use type_registry::ZeroSizedTypes;
 pub mod stream {
     pub use bevy_remote_stream::*;
@@ -34,6 +41,7 @@ impl Plugin for RemoteInspectorPlugin {
         let on_connect = app.main_mut().world_mut().register_system(on_connect);
         let on_disconnect = app.main_mut().world_mut().register_system(on_disconnect);
         let on_data = app.main_mut().world_mut().register_system(on_data);
+        let on_resync = app.main_mut().world_mut().register_system(on_resync);
         app.world_mut().resource_mut::<StreamMethods>().insert(
             "inspector/stream",
             RemoteStreamHandlers {
@@ -41,6 +49,12 @@ impl Plugin for RemoteInspectorPlugin {
                 on_disconnect: Some(on_disconnect),
                 on_connect: Some(on_connect),
                 on_data: Some(on_data),
+                on_resync: Some(on_resync),
+                // A lagging client is better served by a fresh snapshot than by a backlog
+                delivery: StreamDelivery::Resync,
+                // Browsers can't render faster than this, changes in between are coalesced
+                min_interval: Some(Duration::from_secs_f64(1.0 / 60.0)),
+                parse_params: None,
             },
         );
         let mut deep_compare_components = DeepCompareComponents::default();
@@ -55,9 +69,11 @@ impl Plugin for RemoteInspectorPlugin {
 
         app.add_plugins(SchedulesPlugin)
             .init_resource::<TrackedDatas>()
+            .init_resource::<SuspendedSessions>()
             .init_resource::<DisabledComponents>()
             .init_resource::<EntityVisibilities>()
-            .insert_resource(deep_compare_components);
+            .insert_resource(deep_compare_components)
+            .add_systems(Update, expire_sessions);
     }
 }
 
@@ -66,7 +82,12 @@ pub struct RemoteInspectorPlugins;
 impl PluginGroup for RemoteInspectorPlugins {
     fn build(self) -> PluginGroupBuilder {
         PluginGroupBuilder::start::<Self>()
-            .add(RemoteStreamPlugin::default())
+            // Apply commands before gameplay systems, and snapshot after transform propagation
+            .add(
+                RemoteStreamPlugin::default()
+                    .with_request_schedule(First)
+                    .with_update_schedule(Last),
+            )
             .add(RemoteStreamWebSocketPlugin::default())
             .add(RemoteInspectorPlugin)
     }
@@ -79,10 +100,10 @@ fn stream(
     mut zsts: Local<ZeroSizedTypes>,
 ) -> Option<BrpResult> {
     world.resource_scope(|world, mut tracked: Mut<TrackedDatas>| {
+        let tracked = tracked.entry(input.client_id).or_default();
         InspectorContext::run(world, |ctx, world| {
             world.resource_scope(|world, type_registry: Mut<AppTypeRegistry>| {
                 let type_registry = type_registry.read();
-                let tracked = tracked.entry(input.client_id).or_default();
                 tracked.track_type_registry(&mut events, &mut zsts, &type_registry);
                 tracked.track_schedules(&mut events, world, &type_registry);
                 // let new_tables = world
@@ -101,17 +122,17 @@ fn stream(
                 tracked.track_entities(&mut events, world, &type_registry, ctx, &zsts);
             });
         });
-    });
 
-    if events.is_empty() {
-        return None;
-    }
+        if events.is_empty() {
+            return None;
+        }
 
-    let serialized = serde_json::to_value(&*events).unwrap();
+        let result = tracked.batch(world, &events);
 
-    events.clear();
+        events.clear();
 
-    Some(BrpResult::Ok(serialized))
+        Some(result)
+    })
 }
 
 fn on_data(In((_, req)): OnDataHandlerInput, world: &mut World) -> Option<BrpResult> {
@@ -144,14 +165,63 @@ fn on_data(In((_, req)): OnDataHandlerInput, world: &mut World) -> Option<BrpRes
     })
 }
 
-fn on_disconnect(InRef(input): StreamHandlerInputRef, mut tracked: ResMut<TrackedDatas>) {
-    tracked.remove(&input.client_id);
+/// Keep what was sent to the client for a while, so it can resume its session if it reconnects.
+fn on_disconnect(
+    InRef(input): StreamHandlerInputRef,
+    mut tracked: ResMut<TrackedDatas>,
+    mut suspended: ResMut<SuspendedSessions>,
+) {
+    if let Some(tracked) = tracked.remove(&input.client_id) {
+        suspended.suspend(tracked);
+    }
     debug!("Client {:?} disconnected", input.client_id);
 }
 
-fn on_connect(InRef(input): StreamHandlerInputRef) -> Option<BrpResult> {
-    debug!("Client {:?} connected", input.client_id);
-    None
+/// Resume the session given in the `resume` param, or start a new one.
+/// A resumed session only streams what changed while the client was away.
+fn on_connect(InRef(input): StreamHandlerInputRef, world: &mut World) -> Option<BrpResult> {
+    let resumed = ResumeParams::from_params(input.params.as_ref())
+        .and_then(|resume| world.resource_mut::<SuspendedSessions>().resume(&resume));
+    let is_resumed = resumed.is_some();
+    let mut tracked = resumed.unwrap_or_else(|| TrackedData {
+        session: generate_token(),
+        ..default()
+    });
+
+    debug!(
+        "Client {:?} connected, resumed: {is_resumed}",
+        input.client_id
+    );
+
+    let result = tracked.batch(
+        world,
+        &[InspectorEvent::Session {
+            token: tracked.session.clone(),
+            resumed: is_resumed,
+        }],
+    );
+    world
+        .resource_mut::<TrackedDatas>()
+        .insert(input.client_id, tracked);
+
+    Some(result)
+}
+
+/// Forget everything sent to the client so the next `stream` sends a full snapshot.
+fn on_resync(InRef(input): StreamHandlerInputRef, world: &mut World) -> Option<BrpResult> {
+    debug!("Client {:?} resync", input.client_id);
+
+    world.resource_scope(|world, mut tracked: Mut<TrackedDatas>| {
+        let tracked = tracked.entry(input.client_id).or_default();
+        // Keep the sequence going so the client can still detect gaps
+        *tracked = TrackedData {
+            seq: tracked.seq,
+            session: std::mem::take(&mut tracked.session),
+            ..default()
+        };
+
+        Some(tracked.batch(world, &[InspectorEvent::Resync]))
+    })
 }
 
 #[derive(Default)]
@@ -160,10 +230,46 @@ struct TrackedData {
     components: HashSet<ComponentId>,
     entities: EntityHashMap<HashSet<ComponentId>>,
     schedules: bool,
+    /// Sequence number of the last batch sent to the client.
+    seq: u64,
+    /// The change tick of the last run, changes since then are sent on the next one.
+    last_tick: Tick,
+    /// The token the client can resume this session with after reconnecting.
+    session: String,
     // resources: HashSet<ComponentId>,
     // tables: Vec<usize>,
 }
 
+impl TrackedData {
+    fn batch(&mut self, world: &World, events: &[InspectorEvent]) -> BrpResult {
+        self.seq += 1;
+
+        let batch = InspectorBatch {
+            seq: self.seq,
+            frame: world
+                .get_resource::<FrameCount>()
+                .map(|frame| frame.0)
+                .unwrap_or_default(),
+            tick: world.read_change_tick().get(),
+            events,
+        };
+
+        BrpResult::Ok(serde_json::to_value(batch).unwrap())
+    }
+}
+
+/// Events sent to the client in a single response.
+#[derive(Serialize)]
+struct InspectorBatch<'a> {
+    /// Starts at 1 and increases by one for every batch sent to the client.
+    seq: u64,
+    /// The [`FrameCount`] when the batch was created.
+    frame: u32,
+    /// The world change tick when the batch was created.
+    tick: u32,
+    events: &'a [InspectorEvent],
+}
+
 #[derive(Resource, Default, Deref, DerefMut)]
 struct TrackedDatas(HashMap<StreamClientId, TrackedData>);
 
@@ -184,6 +290,13 @@ enum InspectorEvent {
     Schedules {
         schedules: Vec<ScheduleInfo>,
     },
+    /// The client missed some events and must drop its state, a full snapshot follows.
+    Resync,
+    /// The first event of a stream. Unless the session is resumed, the client must drop its state.
+    Session {
+        token: String,
+        resumed: bool,
+    },
     // NewTables {
     //     tables: Vec<usize>,
     // },
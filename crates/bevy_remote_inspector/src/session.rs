@@ -0,0 +1,177 @@
+use bevy::{
+    prelude::*,
+    utils::{Duration, HashMap, Instant},
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::TrackedData;
+
+/// How long the state of a disconnected client is kept so it can resume its session.
+const RESUME_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// The `resume` connect param, sent by a client reconnecting to a previous session.
+#[derive(Deserialize)]
+pub struct ResumeParams {
+    /// The token of the session, sent to the client when it connected.
+    token: String,
+    /// The sequence number of the last batch the client received.
+    seq: u64,
+}
+
+impl ResumeParams {
+    pub fn from_params(params: Option<&Value>) -> Option<Self> {
+        let resume = params?.get("resume")?;
+        serde_json::from_value(resume.clone()).ok()
+    }
+}
+
+/// What was sent to disconnected clients, by session token.
+#[derive(Resource, Default)]
+pub struct SuspendedSessions(HashMap<String, (TrackedData, Instant)>);
+
+impl SuspendedSessions {
+    pub fn suspend(&mut self, tracked: TrackedData) {
+        self.0
+            .insert(tracked.session.clone(), (tracked, Instant::now()));
+    }
+
+    /// Take back the state of a session, as long as the client received everything sent in it.
+    pub fn resume(&mut self, resume: &ResumeParams) -> Option<TrackedData> {
+        let (tracked, _) = self.0.remove(&resume.token)?;
+
+        (tracked.seq == resume.seq).then_some(tracked)
+    }
+}
+
+pub fn expire_sessions(mut sessions: ResMut<SuspendedSessions>) {
+    if sessions.0.is_empty() {
+        return;
+    }
+
+    sessions
+        .0
+        .retain(|_, (_, suspended_at)| suspended_at.elapsed() < RESUME_GRACE_PERIOD);
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy_remote_stream::{testing::TestStreamClient, RemoteStreamPlugin};
+    use serde_json::json;
+
+    use super::*;
+    use crate::RemoteInspectorPlugin;
+
+    fn create_app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            RemoteStreamPlugin::default(),
+            RemoteInspectorPlugin,
+        ));
+        app.finish();
+        app.cleanup();
+        app.update();
+
+        app
+    }
+
+    /// The batches received by the client since the last call.
+    fn batches(client: &mut TestStreamClient) -> Vec<Value> {
+        client
+            .responses()
+            .into_iter()
+            .map(|response| serde_json::to_value(response).unwrap()["result"].clone())
+            .collect()
+    }
+
+    fn events<'a>(batches: &'a [Value], kind: &'a str) -> impl Iterator<Item = &'a Value> {
+        batches
+            .iter()
+            .flat_map(|batch| batch["events"].as_array().unwrap())
+            .filter(move |event| event["kind"] == kind)
+    }
+
+    /// Connect a client, and disconnect it once it received the initial snapshot.
+    /// Returns its session token and the sequence number of its last batch.
+    fn suspended_client(app: &mut App) -> (Value, Value) {
+        let mut client = TestStreamClient::connect(app.world(), "inspector/stream", None);
+        app.update();
+
+        let batches = batches(&mut client);
+        let session = events(&batches, "session").next().unwrap();
+        assert_eq!(session["resumed"], json!(false));
+        assert!(events(&batches, "type_registry").next().is_some());
+        let token = session["token"].clone();
+        let seq = batches.last().unwrap()["seq"].clone();
+
+        client.disconnect();
+        app.update();
+
+        (token, seq)
+    }
+
+    #[test]
+    fn test_resume_session() {
+        let mut app = create_app();
+        let (token, seq) = suspended_client(&mut app);
+
+        let params = json!({ "resume": { "token": token, "seq": seq } });
+        let mut client = TestStreamClient::connect(app.world(), "inspector/stream", Some(params));
+        app.update();
+
+        // The batches continue where they stopped, without sending the snapshot again
+        let batches = batches(&mut client);
+        assert_eq!(batches[0]["seq"], json!(seq.as_u64().unwrap() + 1));
+        let session = events(&batches, "session").next().unwrap();
+        assert_eq!(session["resumed"], json!(true));
+        assert_eq!(session["token"], token);
+        assert!(events(&batches, "type_registry").next().is_none());
+        assert!(events(&batches, "resync").next().is_none());
+    }
+
+    #[test]
+    fn test_resume_stale_seq() {
+        let mut app = create_app();
+        let (token, seq) = suspended_client(&mut app);
+
+        let params = json!({ "resume": { "token": token, "seq": seq.as_u64().unwrap() - 1 } });
+        let mut client = TestStreamClient::connect(app.world(), "inspector/stream", Some(params));
+        app.update();
+
+        // The client missed a batch, it gets a new session and a full snapshot
+        let batches = batches(&mut client);
+        assert_eq!(batches[0]["seq"], json!(1));
+        let session = events(&batches, "session").next().unwrap();
+        assert_eq!(session["resumed"], json!(false));
+        assert_ne!(session["token"], token);
+        assert!(events(&batches, "type_registry").next().is_some());
+    }
+
+    #[test]
+    fn test_expire_sessions() {
+        let mut world = World::new();
+        let mut sessions = SuspendedSessions::default();
+        for (token, suspended_for) in [("recent", Duration::ZERO), ("expired", RESUME_GRACE_PERIOD)]
+        {
+            let tracked = TrackedData {
+                session: token.into(),
+                ..default()
+            };
+            let suspended_at = Instant::now().checked_sub(suspended_for).unwrap();
+            sessions.0.insert(token.into(), (tracked, suspended_at));
+        }
+        world.insert_resource(sessions);
+
+        world.run_system_once(expire_sessions).unwrap();
+
+        let mut sessions = world.resource_mut::<SuspendedSessions>();
+        let resume = |token: &str| ResumeParams {
+            token: token.into(),
+            seq: 0,
+        };
+        assert!(sessions.resume(&resume("expired")).is_none());
+        assert!(sessions.resume(&resume("recent")).is_some());
+    }
+}
@@ -0,0 +1,35 @@
+//! Serve a session recorded with `RemoteStreamWebSocketPlugin::with_recording`,
+//! so the web inspector can browse it without the game running.
+//!
+//! `cargo run -p bevy_remote_stream --example replay -- session.jsonl [connection] [port]`
+
+use anyhow::Context;
+use bevy::log::tracing_subscriber;
+use bevy_remote_stream::{
+    record::StreamReplay,
+    websocket::{DEFAULT_ADDR, DEFAULT_PORT},
+};
+
+const USAGE: &str = "Usage: replay <recording> [connection] [port]";
+
+fn main() -> anyhow::Result<()> {
+    // Show the errors of replay connections
+    tracing_subscriber::fmt::init();
+
+    let mut args = std::env::args().skip(1);
+    let path = args.next().context(USAGE)?;
+
+    let mut replay = StreamReplay::load(&path)?;
+    println!("Recorded connections: {:?}", replay.connections());
+
+    if let Some(connection) = args.next() {
+        replay = replay.with_connection(connection.parse().context(USAGE)?);
+    }
+    let port = match args.next() {
+        Some(port) => port.parse().context(USAGE)?,
+        None => DEFAULT_PORT,
+    };
+
+    println!("Replaying {path} on ws://{DEFAULT_ADDR}:{port}");
+    replay.serve(DEFAULT_ADDR, port)
+}
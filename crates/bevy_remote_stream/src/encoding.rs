@@ -0,0 +1,238 @@
+//! Encodings a WebSocket client can negotiate with the `encoding` query param.
+//!
+//! Responses are sent as text frames with JSON and as binary frames otherwise.
+//! Text frames from the client are always JSON, binary frames use the negotiated encoding.
+//!
+//! With the `deflate` feature, clients can also ask for `compression=deflate` if the server
+//! enables it. Every frame sent by the server is then a binary frame holding the raw deflate
+//! stream of the encoded message (`DecompressionStream("deflate-raw")` in browsers).
+//! This is done per message by the server, not with the `permessage-deflate` extension,
+//! which tungstenite does not implement.
+
+use serde::Serialize;
+use serde_json::Value;
+use tungstenite::Message;
+
+pub const ENCODING_QUERY_KEY: &str = "encoding";
+pub const COMPRESSION_QUERY_KEY: &str = "compression";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    /// `encoding=msgpack`
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    /// `encoding=cbor`
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "json" => Ok(Self::Json),
+            #[cfg(feature = "msgpack")]
+            "msgpack" => Ok(Self::MessagePack),
+            #[cfg(feature = "cbor")]
+            "cbor" => Ok(Self::Cbor),
+            _ => anyhow::bail!("Unsupported encoding `{name}`"),
+        }
+    }
+
+    pub fn encode(self, value: &impl Serialize) -> anyhow::Result<Message> {
+        let message = match self {
+            Self::Json => Message::text(serde_json::to_string(value)?),
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => Message::binary(rmp_serde::to_vec_named(value)?),
+            #[cfg(feature = "cbor")]
+            Self::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes)?;
+                Message::binary(bytes)
+            }
+        };
+
+        Ok(message)
+    }
+
+    /// Returns `None` for frames that do not carry data.
+    pub fn decode(self, message: &Message) -> Option<anyhow::Result<Value>> {
+        match message {
+            Message::Text(text) => Some(serde_json::from_str(text).map_err(Into::into)),
+            Message::Binary(bytes) => Some(self.decode_binary(bytes)),
+            _ => None,
+        }
+    }
+
+    fn decode_binary(self, bytes: &[u8]) -> anyhow::Result<Value> {
+        let value = match self {
+            Self::Json => serde_json::from_slice(bytes)?,
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => rmp_serde::from_slice(bytes)?,
+            #[cfg(feature = "cbor")]
+            Self::Cbor => ciborium::from_reader(bytes)?,
+        };
+
+        Ok(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// `compression=deflate`, with the compression level.
+    #[cfg(feature = "deflate")]
+    Deflate(u32),
+}
+
+impl Compression {
+    /// `level` is the compression level enabled on the server, if any.
+    pub fn from_name(name: &str, level: Option<u32>) -> anyhow::Result<Self> {
+        match (name, level) {
+            ("none", _) => Ok(Self::None),
+            #[cfg(feature = "deflate")]
+            ("deflate", Some(level)) => Ok(Self::Deflate(level)),
+            _ => anyhow::bail!("Unsupported compression `{name}`"),
+        }
+    }
+
+    pub fn compress(self, message: Message) -> anyhow::Result<Message> {
+        match self {
+            Self::None => Ok(message),
+            #[cfg(feature = "deflate")]
+            Self::Deflate(level) => {
+                use std::io::Write;
+
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::new(level));
+                encoder.write_all(&message.into_data())?;
+
+                Ok(Message::binary(encoder.finish()?))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn encodings() -> Vec<Encoding> {
+        vec![
+            Encoding::Json,
+            #[cfg(feature = "msgpack")]
+            Encoding::MessagePack,
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor,
+        ]
+    }
+
+    #[test]
+    fn test_encoding_round_trip() {
+        let value = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": { "seq": 2, "events": [{ "kind": "resync" }], "ratio": 0.5, "none": null },
+        });
+
+        for encoding in encodings() {
+            let message = encoding.encode(&value).unwrap();
+            assert_eq!(message.is_text(), encoding == Encoding::Json);
+            assert_eq!(encoding.decode(&message).unwrap().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_decode_text_is_json() {
+        let message = Message::text(r#"{"method":"test"}"#);
+
+        for encoding in encodings() {
+            let value = encoding.decode(&message).unwrap().unwrap();
+            assert_eq!(value, json!({ "method": "test" }));
+        }
+
+        assert!(Encoding::Json.decode(&Message::Ping(vec![])).is_none());
+        assert!(Encoding::Json
+            .decode(&Message::text("not json"))
+            .unwrap()
+            .is_err());
+    }
+
+    #[test]
+    fn test_encoding_from_name() {
+        assert_eq!(Encoding::from_name("json").unwrap(), Encoding::Json);
+        #[cfg(feature = "msgpack")]
+        assert_eq!(
+            Encoding::from_name("msgpack").unwrap(),
+            Encoding::MessagePack
+        );
+        #[cfg(not(feature = "msgpack"))]
+        assert!(Encoding::from_name("msgpack").is_err());
+        #[cfg(feature = "cbor")]
+        assert_eq!(Encoding::from_name("cbor").unwrap(), Encoding::Cbor);
+        #[cfg(not(feature = "cbor"))]
+        assert!(Encoding::from_name("cbor").is_err());
+        assert!(Encoding::from_name("xml").is_err());
+        assert!(Encoding::from_name("JSON").is_err());
+    }
+
+    #[test]
+    fn test_compression_from_name() {
+        assert_eq!(
+            Compression::from_name("none", None).unwrap(),
+            Compression::None
+        );
+        assert_eq!(
+            Compression::from_name("none", Some(6)).unwrap(),
+            Compression::None
+        );
+        #[cfg(feature = "deflate")]
+        assert_eq!(
+            Compression::from_name("deflate", Some(6)).unwrap(),
+            Compression::Deflate(6)
+        );
+        #[cfg(not(feature = "deflate"))]
+        assert!(Compression::from_name("deflate", Some(6)).is_err());
+        // Not enabled on the server
+        assert!(Compression::from_name("deflate", None).is_err());
+        assert!(Compression::from_name("gzip", Some(6)).is_err());
+    }
+
+    #[test]
+    fn test_no_compression() {
+        let message = Message::text("hello");
+        assert_eq!(
+            Compression::None.compress(message.clone()).unwrap(),
+            message
+        );
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn test_deflate_round_trip() {
+        use std::io::Read;
+
+        let message = Encoding::Json
+            .encode(&json!({ "events": vec!["repeated"; 100] }))
+            .unwrap();
+        let data = message.clone().into_data();
+
+        for level in [0, 6, 9] {
+            let compressed = Compression::Deflate(level)
+                .compress(message.clone())
+                .unwrap();
+            assert!(compressed.is_binary());
+
+            // Raw deflate, without a zlib header
+            let mut decompressed = Vec::new();
+            flate2::read::DeflateDecoder::new(&compressed.into_data()[..])
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, data);
+        }
+    }
+}
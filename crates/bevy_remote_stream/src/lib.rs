@@ -1,22 +1,86 @@
 #[cfg(feature = "websocket")]
+mod encoding;
+#[cfg(any(feature = "tcp", all(unix, feature = "unix")))]
+mod ndjson;
+#[cfg(feature = "websocket")]
+pub mod record;
+#[cfg(feature = "websocket")]
+mod subscription;
+#[cfg(feature = "tcp")]
+pub mod tcp;
+pub mod testing;
+#[cfg(feature = "tls")]
+pub mod tls;
+mod typed;
+#[cfg(all(unix, feature = "unix"))]
+pub mod unix;
+#[cfg(feature = "websocket")]
 pub mod websocket;
 
-use std::sync::RwLock;
+use std::{
+    any::Any,
+    collections::VecDeque,
+    fmt,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        RwLock,
+    },
+};
 
 use bevy::{
-    ecs::system::SystemId,
+    ecs::{
+        schedule::{InternedScheduleLabel, ScheduleLabel},
+        system::SystemId,
+    },
     prelude::*,
-    remote::{error_codes, BrpError, BrpRequest, BrpResponse, BrpResult},
-    utils::HashMap,
+    remote::{BrpError, BrpRequest, BrpResponse, BrpResult, RemoteMethodSystemId, RemoteMethods},
+    tasks::IoTaskPool,
+    utils::{Duration, HashMap, Instant},
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use smol::channel::{self, Receiver, Sender};
+use smol::channel::{self, Receiver, Sender, TrySendError};
+
+pub use typed::*;
+
+/// Error codes used by stream methods, in addition to the ones defined by the BRP.
+pub mod error_codes {
+    pub use bevy::remote::error_codes::*;
+
+    /// The client fell behind and the stream can not recover its state.
+    pub const STREAM_LAGGED: i16 = -23501;
+
+    /// The client did not provide valid credentials.
+    pub const UNAUTHORIZED: i16 = -23502;
+
+    /// The server has no room for another client.
+    pub const SERVER_FULL: i16 = -23503;
+
+    /// The web page the client runs in is not allowed to connect.
+    pub const ORIGIN_NOT_ALLOWED: i16 = -23504;
+}
 
 const CHANNEL_SIZE: usize = 16;
 
+/// How many responses a [`StreamDelivery::Backpressure`] stream queues before it resyncs instead.
+const MAX_PENDING_RESPONSES: usize = 256;
+
+/// How long a server waits before accepting connections again after an error.
+#[cfg(any(feature = "websocket", feature = "tcp", all(unix, feature = "unix")))]
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The largest request accepted, as the body of a one-shot `POST` or a line of the socket
+/// transports.
+#[cfg(any(feature = "websocket", feature = "tcp", all(unix, feature = "unix")))]
+const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
 pub struct RemoteStreamPlugin {
     methods: RwLock<Vec<(String, RemoteStreamHandlersBuilder)>>,
+    forward_remote_methods: bool,
+    request_schedule: InternedScheduleLabel,
+    update_schedule: InternedScheduleLabel,
 }
 
 impl RemoteStreamPlugin {
@@ -28,20 +92,64 @@ impl RemoteStreamPlugin {
     pub fn with_method(
         mut self,
         name: impl Into<String>,
-        handlers: RemoteStreamHandlersBuilder,
+        handlers: impl Into<RemoteStreamHandlersBuilder>,
     ) -> Self {
         self.methods
             .get_mut()
             .unwrap()
-            .push((name.into(), handlers));
+            .push((name.into(), handlers.into()));
+        self
+    }
+
+    /// Also serve the methods registered in bevy's [`RemoteMethods`] (`bevy/get`, `bevy/query`, ...),
+    /// so standard BRP calls work over the stream transports without a second server.
+    ///
+    /// Requests sent to a stream whose method is in [`RemoteMethods`] are answered directly instead
+    /// of going to its `on_data` handler, and transports accept one-shot requests (e.g. HTTP `POST`).
+    /// Requires [`RemotePlugin`](bevy::remote::RemotePlugin), `RemoteHttpPlugin` is not needed.
+    /// Only instant methods are supported, watching methods are rejected.
+    #[must_use]
+    pub fn with_remote_methods(mut self) -> Self {
+        self.forward_remote_methods = true;
         self
     }
+
+    /// Set the schedule where messages from clients are processed: connects, disconnects and
+    /// requests sent to streams (e.g. inspector commands). Defaults to [`Update`].
+    /// Use [`RemoteStreamSet::ProcessRequests`] to order it within the schedule.
+    #[must_use]
+    pub fn with_request_schedule(mut self, schedule: impl ScheduleLabel) -> Self {
+        self.request_schedule = schedule.intern();
+        self
+    }
+
+    /// Set the schedule where the `update` handlers of streams run. Defaults to [`Update`].
+    /// Use [`RemoteStreamSet::Update`] to order it within the schedule, e.g. after gameplay systems.
+    #[must_use]
+    pub fn with_update_schedule(mut self, schedule: impl ScheduleLabel) -> Self {
+        self.update_schedule = schedule.intern();
+        self
+    }
+}
+
+/// The system sets of [`RemoteStreamPlugin`], in the schedules chosen with
+/// [`RemoteStreamPlugin::with_request_schedule`] and [`RemoteStreamPlugin::with_update_schedule`].
+/// When both are in the same schedule, requests are processed first.
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RemoteStreamSet {
+    /// Processes connects, disconnects and requests sent to streams.
+    ProcessRequests,
+    /// Runs the `update` handlers of streams and sends their responses.
+    Update,
 }
 
 impl Default for RemoteStreamPlugin {
     fn default() -> Self {
         Self {
             methods: RwLock::new(vec![]),
+            forward_remote_methods: false,
+            request_schedule: Update.intern(),
+            update_schedule: Update.intern(),
         }
     }
 }
@@ -69,15 +177,47 @@ impl Plugin for RemoteStreamPlugin {
                     on_data: systems
                         .on_data
                         .map(|sys| app.main_mut().world_mut().register_boxed_system(sys)),
+                    on_resync: systems
+                        .on_resync
+                        .map(|sys| app.main_mut().world_mut().register_boxed_system(sys)),
+                    delivery: systems.delivery,
+                    min_interval: systems.min_interval,
+                    parse_params: systems.parse_params,
                 },
             );
         }
 
+        if self.forward_remote_methods {
+            app.insert_resource(ForwardRemoteMethods);
+        }
+
         app.insert_resource(stream_methods)
             .init_resource::<ActiveStreams>()
+            .init_resource::<StreamBroadcasts>()
+            .add_event::<StreamServerEvent>()
             .add_systems(PreStartup, setup_channel)
-            .add_systems(Update, process_remote_requests)
-            .add_systems(Update, on_app_exit.run_if(on_event::<AppExit>));
+            .add_systems(Update, forward_server_events)
+            .add_systems(
+                self.request_schedule,
+                process_remote_requests.in_set(RemoteStreamSet::ProcessRequests),
+            )
+            .add_systems(
+                self.update_schedule,
+                update_streams.in_set(RemoteStreamSet::Update),
+            )
+            .add_systems(
+                Last,
+                on_app_exit
+                    .after(RemoteStreamSet::Update)
+                    .run_if(on_event::<AppExit>),
+            );
+
+        if self.request_schedule == self.update_schedule {
+            app.configure_sets(
+                self.update_schedule,
+                RemoteStreamSet::ProcessRequests.before(RemoteStreamSet::Update),
+            );
+        }
     }
 }
 
@@ -86,12 +226,50 @@ pub struct RemoteStreamHandlers {
     pub on_connect: Option<StreamHandler>,
     pub on_disconnect: Option<SystemId<StreamHandlerInputRef<'static>>>,
     pub on_data: Option<OnDataHandler>,
+    /// Called once a lagging client has caught up after responses were dropped.
+    /// The handler should reset its per-client state so the next `update` sends everything again.
+    pub on_resync: Option<StreamHandler>,
     pub update: StreamHandler,
+    pub delivery: StreamDelivery,
+    /// The minimum time between two runs of `update` for a client, `None` runs it every frame.
+    /// Clients can ask for a lower rate with `max_update_rate` (in Hz) in their connect params.
+    pub min_interval: Option<Duration>,
+    /// Deserializes the connect params for typed handlers, see [`TypedStreamHandlersBuilder`].
+    pub parse_params: Option<ParamsParser>,
+}
+
+/// What to do when a client does not read responses as fast as the stream produces them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StreamDelivery {
+    /// Queue the responses that do not fit in the channel and stop calling `update` until the
+    /// client has drained them. Nothing is dropped, so `update` should coalesce changes between runs.
+    /// If the queue still grows too large, it is dropped and the stream resyncs like with
+    /// [`StreamDelivery::Resync`].
+    #[default]
+    Backpressure,
+    /// Drop the responses that do not fit in the channel. Once the client has drained the channel,
+    /// `on_resync` is called so the stream can send its full state again.
+    /// Without an `on_resync` handler the stream is closed with [`error_codes::STREAM_LAGGED`].
+    Resync,
 }
 
 pub struct StreamHandlerInput {
     pub client_id: StreamClientId,
     pub params: Option<Value>,
+    /// The [`TypedStreamInput`] of typed handlers.
+    typed: Option<Box<dyn Any + Send + Sync>>,
+}
+
+impl StreamHandlerInput {
+    /// The input of an untyped handler, e.g. to run it with
+    /// [`World::run_system_with_input`] in tests.
+    pub fn new(client_id: StreamClientId, params: Option<Value>) -> Self {
+        Self {
+            client_id,
+            params,
+            typed: None,
+        }
+    }
 }
 
 pub type StreamHandlerInputRef<'a> = InRef<'a, StreamHandlerInput>;
@@ -101,22 +279,38 @@ pub type OnDataHandler = SystemId<OnDataHandlerInput, Option<BrpResult>>;
 
 #[derive(Debug)]
 pub struct RemoteStreamHandlersBuilder {
-    on_connect:
+    pub(crate) on_connect:
+        Option<Box<dyn System<In = StreamHandlerInputRef<'static>, Out = Option<BrpResult>>>>,
+    pub(crate) on_disconnect:
+        Option<Box<dyn System<In = StreamHandlerInputRef<'static>, Out = ()>>>,
+    pub(crate) on_data: Option<Box<dyn System<In = OnDataHandlerInput, Out = Option<BrpResult>>>>,
+    pub(crate) on_resync:
         Option<Box<dyn System<In = StreamHandlerInputRef<'static>, Out = Option<BrpResult>>>>,
-    on_disconnect: Option<Box<dyn System<In = StreamHandlerInputRef<'static>, Out = ()>>>,
-    on_data: Option<Box<dyn System<In = OnDataHandlerInput, Out = Option<BrpResult>>>>,
     update: Box<dyn System<In = StreamHandlerInputRef<'static>, Out = Option<BrpResult>>>,
+    delivery: StreamDelivery,
+    min_interval: Option<Duration>,
+    pub(crate) parse_params: Option<ParamsParser>,
 }
 
 impl RemoteStreamHandlersBuilder {
     pub fn new<M>(
         update: impl IntoSystem<StreamHandlerInputRef<'static>, Option<BrpResult>, M>,
+    ) -> Self {
+        Self::from_boxed(Box::new(IntoSystem::into_system(update)))
+    }
+
+    pub(crate) fn from_boxed(
+        update: Box<dyn System<In = StreamHandlerInputRef<'static>, Out = Option<BrpResult>>>,
     ) -> Self {
         Self {
             on_connect: None,
             on_disconnect: None,
             on_data: None,
-            update: Box::new(IntoSystem::into_system(update)),
+            on_resync: None,
+            update,
+            delivery: StreamDelivery::default(),
+            min_interval: None,
+            parse_params: None,
         }
     }
 
@@ -135,6 +329,57 @@ impl RemoteStreamHandlersBuilder {
         self.on_disconnect = Some(Box::new(IntoSystem::into_system(system)));
         self
     }
+
+    /// Set the handler run when a lagging client caught up after responses were dropped, with
+    /// [`StreamDelivery::Resync`] or when the [`StreamDelivery::Backpressure`] queue overflowed.
+    /// It runs before `update` in the same frame, and should reset the per-client state so the
+    /// stream sends its full state again. Without it, the stream is closed with
+    /// [`error_codes::STREAM_LAGGED`].
+    pub fn on_resync<M>(
+        mut self,
+        system: impl IntoSystem<StreamHandlerInputRef<'static>, Option<BrpResult>, M>,
+    ) -> Self {
+        self.on_resync = Some(Box::new(IntoSystem::into_system(system)));
+        self
+    }
+
+    /// Set the handler of requests sent to the stream by the client.
+    /// Returning `None` sends no response.
+    pub fn on_data<M>(
+        mut self,
+        system: impl IntoSystem<OnDataHandlerInput, Option<BrpResult>, M>,
+    ) -> Self {
+        self.on_data = Some(Box::new(IntoSystem::into_system(system)));
+        self
+    }
+
+    /// Set how responses are delivered to clients that fall behind, see [`StreamDelivery`].
+    pub fn with_delivery(mut self, delivery: StreamDelivery) -> Self {
+        self.delivery = delivery;
+        self
+    }
+
+    /// Wait at least `interval` between two runs of `update` for a client.
+    /// `update` should coalesce the changes made in between, as they are not replayed.
+    pub fn with_min_interval(mut self, interval: Duration) -> Self {
+        self.min_interval = Some(interval);
+        self
+    }
+
+    /// Run `update` at most `hz` times per second for a client, see [`Self::with_min_interval`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hz` is not a positive finite number.
+    pub fn with_max_rate(self, hz: f64) -> Self {
+        assert!(
+            hz.is_finite() && hz > 0.0,
+            "The max update rate must be a positive finite number, got {hz}"
+        );
+
+        // A rate too low for a `Duration` means never
+        self.with_min_interval(Duration::try_from_secs_f64(1.0 / hz).unwrap_or(Duration::MAX))
+    }
 }
 
 /// Holds all implementations of methods known to the server.
@@ -154,12 +399,215 @@ impl StreamMethods {
     }
 }
 
-#[derive(Resource, Deref, DerefMut)]
-pub struct StreamSender(Sender<StreamMessage>);
+/// The entry point for transports: every connection of a transport gets its own [`StreamClient`].
+#[derive(Resource, Deref, DerefMut, Clone)]
+pub struct StreamSender {
+    #[deref]
+    sender: Sender<StreamMessage>,
+    events: Sender<StreamServerEvent>,
+}
+
+impl StreamSender {
+    /// Create a client with an id that is unique across all transports.
+    pub fn client(&self) -> StreamClient {
+        StreamClient {
+            id: StreamClientId::next(),
+            sender: self.sender.clone(),
+            connected: false,
+        }
+    }
+
+    /// Report a change in the lifecycle of a server, it is sent to the app as a [`StreamServerEvent`].
+    pub fn report(&self, event: StreamServerEvent) {
+        let _ = self.events.try_send(event);
+    }
+}
+
+/// The lifecycle of the stream servers and their clients.
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub enum StreamServerEvent {
+    /// A server is accepting connections on this address.
+    Listening(ServerAddress),
+    /// A server could not listen on this address, it is not running.
+    BindFailed {
+        address: ServerAddress,
+        error: String,
+    },
+    /// A server failed to accept a connection, it keeps running.
+    AcceptFailed {
+        address: ServerAddress,
+        error: String,
+    },
+    /// A server was stopped and closed its listener.
+    Stopped(ServerAddress),
+    /// A client opened a stream.
+    ClientConnected(StreamClientId),
+    /// A stream of a client was closed, by the client or by the server.
+    ClientDisconnected(StreamClientId),
+}
+
+/// Where a server listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerAddress {
+    Socket(SocketAddr),
+    Path(PathBuf),
+}
+
+impl fmt::Display for ServerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Socket(address) => address.fmt(f),
+            Self::Path(path) => path.display().fmt(f),
+        }
+    }
+}
+
+impl From<SocketAddr> for ServerAddress {
+    fn from(address: SocketAddr) -> Self {
+        Self::Socket(address)
+    }
+}
+
+impl From<PathBuf> for ServerAddress {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+/// Bind `port`, or the first free one of the `fallback_ports` ports after it.
+#[cfg(any(feature = "websocket", feature = "tcp"))]
+fn bind_tcp(
+    address: std::net::IpAddr,
+    port: u16,
+    fallback_ports: u16,
+) -> std::io::Result<smol::Async<std::net::TcpListener>> {
+    let mut port = port;
+    let last_port = port.saturating_add(fallback_ports);
+
+    loop {
+        match smol::Async::<std::net::TcpListener>::bind((address, port)) {
+            Err(error) if error.kind() == std::io::ErrorKind::AddrInUse && port < last_port => {
+                port += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// A connection of a transport to the stream methods.
+///
+/// A transport calls [`StreamClient::connect`] with the request that opens the stream,
+/// forwards everything the client sends with [`StreamClient::send`] and writes back the
+/// [`BrpResponse`]s it receives. Dropping the client disconnects it.
+pub struct StreamClient {
+    id: StreamClientId,
+    sender: Sender<StreamMessage>,
+    connected: bool,
+}
+
+impl StreamClient {
+    pub fn id(&self) -> StreamClientId {
+        self.id
+    }
+
+    /// Open a stream for `request`. Every response of the stream is sent to `responses`.
+    pub async fn connect(
+        &mut self,
+        request: BrpRequest,
+        responses: Sender<BrpResponse>,
+    ) -> anyhow::Result<()> {
+        self.sender
+            .send(StreamMessage {
+                client_id: self.id,
+                kind: StreamMessageKind::Connect(
+                    request.id,
+                    BrpStreamMessage {
+                        method: request.method,
+                        params: request.params,
+                        sender: responses,
+                    },
+                ),
+            })
+            .await?;
+        self.connected = true;
+
+        Ok(())
+    }
+
+    /// Send a one-shot request, answered by [`RemoteMethods`] when the plugin is configured
+    /// with [`RemoteStreamPlugin::with_remote_methods`]. The client does not need to be connected.
+    pub async fn request(&self, request: BrpRequest) -> anyhow::Result<BrpResponse> {
+        let (sender, receiver) = channel::bounded(1);
+        self.sender
+            .send(StreamMessage {
+                client_id: self.id,
+                kind: StreamMessageKind::Request(request, sender),
+            })
+            .await?;
+
+        Ok(receiver.recv().await?)
+    }
+
+    /// Send a request of the client to its stream.
+    pub async fn send(&self, data: Value) -> anyhow::Result<()> {
+        self.sender
+            .send(StreamMessage {
+                client_id: self.id,
+                kind: StreamMessageKind::Data(data),
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Close the stream, its `on_disconnect` handler will run.
+    pub async fn disconnect(mut self) -> anyhow::Result<()> {
+        if !self.connected {
+            return Ok(());
+        }
+
+        self.connected = false;
+        self.sender
+            .send(StreamMessage {
+                client_id: self.id,
+                kind: StreamMessageKind::Disconnect,
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl Drop for StreamClient {
+    fn drop(&mut self) {
+        if !self.connected {
+            return;
+        }
+
+        let message = StreamMessage {
+            client_id: self.id,
+            kind: StreamMessageKind::Disconnect,
+        };
+
+        if let Err(TrySendError::Full(message)) = self.sender.try_send(message) {
+            // Can't wait here, let the IO pool deliver it once the app has room
+            if let Some(pool) = IoTaskPool::try_get() {
+                let sender = self.sender.clone();
+                pool.spawn(async move {
+                    let _ = sender.send(message).await;
+                })
+                .detach();
+            }
+        }
+    }
+}
 
 #[derive(Resource, Deref, DerefMut)]
 pub struct StreamReceiver(Receiver<StreamMessage>);
 
+#[derive(Resource, Deref, DerefMut)]
+struct StreamServerEventReceiver(Receiver<StreamServerEvent>);
+
 pub struct StreamMessage {
     client_id: StreamClientId,
     kind: StreamMessageKind,
@@ -183,44 +631,270 @@ pub enum StreamMessageKind {
     Connect(Option<Value>, BrpStreamMessage),
     Disconnect,
     Data(Value),
+    Request(BrpRequest, Sender<BrpResponse>),
+}
+
+/// Sends results to the clients of stream methods from game code, e.g. "level loaded".
+///
+/// Results are delivered in [`RemoteStreamSet::Update`], before the `update` handlers run, as
+/// responses to the request that opened the stream. Like a result returned by `update`, an error
+/// closes the stream.
+#[derive(Resource, Default)]
+pub struct StreamBroadcasts {
+    pending: Vec<(BroadcastTarget, BrpResult)>,
+}
+
+enum BroadcastTarget {
+    Method(String),
+    Client(StreamClientId),
+}
+
+impl StreamBroadcasts {
+    /// Send `result` to every client of the stream method `method`.
+    pub fn send_to_method(&mut self, method: impl Into<String>, result: BrpResult) {
+        self.pending
+            .push((BroadcastTarget::Method(method.into()), result));
+    }
+
+    /// Send `result` to the client `client_id`, whatever its stream method.
+    pub fn send_to_client(&mut self, client_id: StreamClientId, result: BrpResult) {
+        self.pending
+            .push((BroadcastTarget::Client(client_id), result));
+    }
+}
+
+impl BroadcastTarget {
+    fn matches(&self, client_id: StreamClientId, stream: &ActiveStream) -> bool {
+        match self {
+            Self::Method(method) => *method == stream.method,
+            Self::Client(id) => *id == client_id,
+        }
+    }
 }
 
+/// Inserted when the plugin forwards requests to [`RemoteMethods`].
+#[derive(Resource)]
+struct ForwardRemoteMethods;
+
 #[derive(Resource, Deref, DerefMut, Default)]
 struct ActiveStreams(HashMap<StreamClientId, ActiveStream>);
 
 struct ActiveStream {
+    method: String,
     request_id: Option<Value>,
     sender: ActiveStreamSender,
     input: StreamHandlerInput,
     on_update: StreamHandler,
     on_disconnect: Option<SystemId<StreamHandlerInputRef<'static>>>,
     on_data: Option<OnDataHandler>,
+    on_resync: Option<StreamHandler>,
+    min_interval: Option<Duration>,
+    last_update: Option<Instant>,
+}
+
+impl ActiveStream {
+    /// Whether `update` is due, given the minimum interval between runs.
+    fn should_update(&mut self) -> bool {
+        let Some(min_interval) = self.min_interval else {
+            return true;
+        };
+
+        let now = Instant::now();
+        if self
+            .last_update
+            .is_some_and(|last_update| now.duration_since(last_update) < min_interval)
+        {
+            return false;
+        }
+
+        self.last_update = Some(now);
+        true
+    }
+}
+
+/// The connect param clients use to lower the update rate of a stream, in Hz.
+const MAX_UPDATE_RATE_PARAM: &str = "max_update_rate";
+
+/// The interval between runs of `update`, the longest of the method's and the client's.
+fn min_interval(method: Option<Duration>, params: Option<&Value>) -> Option<Duration> {
+    let requested = params
+        .and_then(|params| params.get(MAX_UPDATE_RATE_PARAM))
+        .and_then(Value::as_f64)
+        .filter(|hz| *hz > 0.0)
+        // Ignore rates that don't fit in a `Duration`, the client controls them
+        .and_then(|hz| Duration::try_from_secs_f64(1.0 / hz).ok());
+
+    method.max(requested)
 }
 
-struct ActiveStreamSender(Sender<BrpResponse>);
+struct ActiveStreamSender {
+    sender: Sender<BrpResponse>,
+    delivery: StreamDelivery,
+    /// Responses waiting for room in the channel, only used with [`StreamDelivery::Backpressure`].
+    pending: VecDeque<BrpResponse>,
+    /// Whether a response was dropped since the last resync.
+    lagged: bool,
+}
+
+enum CatchUp {
+    /// The client has room for new data.
+    Ready,
+    /// The client is still draining old data, `update` should not run.
+    Behind,
+    /// The client caught up after dropping data, the stream has to resync.
+    Resync,
+}
 
 impl ActiveStreamSender {
-    fn send(&self, id: Option<Value>, result: BrpResult) -> bool {
-        let res = self.0.force_send(BrpResponse::new(id, result));
+    fn new(sender: Sender<BrpResponse>, delivery: StreamDelivery) -> Self {
+        Self {
+            sender,
+            delivery,
+            pending: VecDeque::new(),
+            lagged: false,
+        }
+    }
+
+    /// Returns `false` when the channel is closed.
+    fn send(&mut self, id: Option<Value>, result: BrpResult) -> bool {
+        let response = BrpResponse::new(id, result);
 
-        match res {
-            Ok(Some(_)) => {
-                warn!("Channel queue is full, dropping response. Consider increasing the channel size.");
+        if !self.pending.is_empty() {
+            self.queue(response);
+            return !self.sender.is_closed();
+        }
+
+        match self.sender.try_send(response) {
+            Ok(()) => true,
+            Err(TrySendError::Full(response)) => {
+                match self.delivery {
+                    StreamDelivery::Backpressure => self.queue(response),
+                    StreamDelivery::Resync => {
+                        if !self.lagged {
+                            warn!(
+                                "Client is lagging behind, dropping responses until it catches up"
+                            );
+                        }
+                        self.lagged = true;
+                    }
+                }
+
+                true
             }
-            _ => {}
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Queue a response until the channel has room, or drop the queue and resync once it is full.
+    fn queue(&mut self, response: BrpResponse) {
+        if self.pending.len() < MAX_PENDING_RESPONSES {
+            self.pending.push_back(response);
+            return;
+        }
+
+        warn!(
+            "Client is too far behind, dropping {} queued responses to resync",
+            self.pending.len()
+        );
+        self.pending.clear();
+        self.lagged = true;
+    }
+
+    /// Deliver the queued responses in the background, the channel closes once they are sent.
+    fn flush(self) {
+        let Self {
+            sender, pending, ..
+        } = self;
+
+        if pending.is_empty() {
+            return;
+        }
+
+        if let Some(pool) = IoTaskPool::try_get() {
+            pool.spawn(async move {
+                for response in pending {
+                    if sender.send(response).await.is_err() {
+                        return;
+                    }
+                }
+            })
+            .detach();
+        }
+    }
+
+    /// Flush queued responses into the channel and report whether the client can take new data.
+    fn catch_up(&mut self) -> CatchUp {
+        while let Some(response) = self.pending.pop_front() {
+            match self.sender.try_send(response) {
+                Ok(()) => {}
+                Err(TrySendError::Full(response)) => {
+                    self.pending.push_front(response);
+                    return CatchUp::Behind;
+                }
+                Err(TrySendError::Closed(_)) => {
+                    self.pending.clear();
+                    return CatchUp::Ready;
+                }
+            }
+        }
+
+        if !self.lagged {
+            return CatchUp::Ready;
+        }
+
+        if !self.sender.is_empty() {
+            return CatchUp::Behind;
         }
 
-        return res.is_ok();
+        self.lagged = false;
+        CatchUp::Resync
     }
 }
 
 #[derive(Default, Clone, Copy, Hash, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct StreamClientId(usize);
 
+impl StreamClientId {
+    fn next() -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
 fn setup_channel(mut commands: Commands) {
     let (sender, receiver) = channel::bounded(CHANNEL_SIZE);
-    commands.insert_resource(StreamSender(sender));
+    let (event_sender, event_receiver) = channel::unbounded();
+    commands.insert_resource(StreamSender {
+        sender,
+        events: event_sender,
+    });
     commands.insert_resource(StreamReceiver(receiver));
+    commands.insert_resource(StreamServerEventReceiver(event_receiver));
+}
+
+fn forward_server_events(
+    receiver: Option<Res<StreamServerEventReceiver>>,
+    mut events: EventWriter<StreamServerEvent>,
+) {
+    let Some(receiver) = receiver else {
+        return;
+    };
+
+    while let Ok(event) = receiver.try_recv() {
+        match &event {
+            StreamServerEvent::Listening(address) => debug!("Stream server listening on {address}"),
+            StreamServerEvent::BindFailed { address, error } => {
+                error!("Stream server failed to listen on {address}: {error}")
+            }
+            StreamServerEvent::AcceptFailed { address, error } => {
+                warn!("Stream server on {address} failed to accept a connection: {error}")
+            }
+            _ => {}
+        }
+
+        events.send(event);
+    }
 }
 
 fn process_remote_requests(world: &mut World) {
@@ -244,27 +918,48 @@ fn process_remote_requests(world: &mut World) {
                         return;
                     };
 
+                    let min_interval = min_interval(handler.min_interval, message.params.as_ref());
+                    let typed = match handler.parse_params {
+                        Some(parse) => {
+                            match parse(stream_message.client_id, message.params.as_ref()) {
+                                Ok(typed) => Some(typed),
+                                Err(err) => {
+                                    let _ = message
+                                        .sender
+                                        .force_send(BrpResponse::new(req_id, Err(err)));
+                                    return;
+                                }
+                            }
+                        }
+                        None => None,
+                    };
                     let input = StreamHandlerInput {
                         client_id: stream_message.client_id,
                         params: message.params,
+                        typed,
                     };
-                    let sender = ActiveStreamSender(message.sender);
+                    let mut sender = ActiveStreamSender::new(message.sender, handler.delivery);
 
                     if let Some(on_connect) = handler.on_connect {
-                        if run_handler(world, on_connect, &input, &sender, req_id.as_ref()) {
+                        if run_handler(world, on_connect, &input, &mut sender, req_id.as_ref()) {
                             return;
                         }
                     }
 
+                    world.send_event(StreamServerEvent::ClientConnected(stream_message.client_id));
                     world.resource_mut::<ActiveStreams>().insert(
                         stream_message.client_id,
                         ActiveStream {
+                            method: message.method,
                             request_id: req_id,
                             input,
                             sender,
                             on_update: handler.update,
                             on_disconnect: handler.on_disconnect,
                             on_data: handler.on_data,
+                            on_resync: handler.on_resync,
+                            min_interval,
+                            last_update: None,
                         },
                     );
                 }
@@ -277,11 +972,14 @@ fn process_remote_requests(world: &mut World) {
                         if let Some(on_disconnect) = stream.on_disconnect {
                             let _ = world.run_system_with_input(on_disconnect, &stream.input);
                         }
+                        world.send_event(StreamServerEvent::ClientDisconnected(
+                            stream_message.client_id,
+                        ));
                     }
                 }
                 StreamMessageKind::Data(value) => {
-                    world.resource_scope(|world, active_streams: Mut<ActiveStreams>| {
-                        let stream = active_streams.get(&stream_message.client_id);
+                    world.resource_scope(|world, mut active_streams: Mut<ActiveStreams>| {
+                        let stream = active_streams.get_mut(&stream_message.client_id);
 
                         let Some(stream) = stream else {
                             return;
@@ -302,11 +1000,19 @@ fn process_remote_requests(world: &mut World) {
                             }
                         };
 
+                        let request_id = request.id.clone();
+
+                        if let Some(result) = run_remote_method(world, &request) {
+                            if request_id.is_some() {
+                                stream.sender.send(request_id, result);
+                            }
+                            return;
+                        }
+
                         let Some(on_data) = stream.on_data else {
                             return;
                         };
 
-                        let request_id = request.id.clone();
                         let result = world
                             .run_system_with_input(on_data, (stream_message.client_id, request));
 
@@ -335,19 +1041,87 @@ fn process_remote_requests(world: &mut World) {
                         }
                     })
                 }
+                StreamMessageKind::Request(request, sender) => {
+                    let result = run_remote_method(world, &request).unwrap_or_else(|| {
+                        Err(BrpError {
+                            code: error_codes::METHOD_NOT_FOUND,
+                            message: format!("Method `{}` not found", request.method),
+                            data: None,
+                        })
+                    });
+
+                    let _ = sender.force_send(BrpResponse::new(request.id, result));
+                }
             },
         );
     }
+}
+
+fn update_streams(world: &mut World) {
+    let broadcasts = std::mem::take(&mut world.resource_mut::<StreamBroadcasts>().pending);
 
     world.resource_scope(|world, mut streams: Mut<ActiveStreams>| {
         let to_remove = streams
-            .iter()
+            .iter_mut()
             .filter_map(|(client_id, stream)| {
+                for (target, result) in &broadcasts {
+                    if !target.matches(*client_id, stream) {
+                        continue;
+                    }
+
+                    let result = match result {
+                        Ok(value) => Ok(value.clone()),
+                        Err(err) => Err(BrpError {
+                            code: err.code,
+                            message: err.message.clone(),
+                            data: err.data.clone(),
+                        }),
+                    };
+                    let is_err = result.is_err();
+
+                    if !stream.sender.send(stream.request_id.clone(), result) || is_err {
+                        return Some(*client_id);
+                    }
+                }
+
+                match stream.sender.catch_up() {
+                    CatchUp::Ready => {}
+                    CatchUp::Behind => return None,
+                    CatchUp::Resync => {
+                        let Some(on_resync) = stream.on_resync else {
+                            stream.sender.send(
+                                stream.request_id.clone(),
+                                Err(BrpError {
+                                    code: error_codes::STREAM_LAGGED,
+                                    message: "Client fell behind and the stream can not resync"
+                                        .into(),
+                                    data: None,
+                                }),
+                            );
+                            return Some(*client_id);
+                        };
+
+                        if run_handler(
+                            world,
+                            on_resync,
+                            &stream.input,
+                            &mut stream.sender,
+                            stream.request_id.as_ref(),
+                        ) {
+                            return Some(*client_id);
+                        }
+                    }
+                }
+
+                if !stream.should_update() {
+                    return None;
+                }
+
                 run_handler(
                     world,
                     stream.on_update,
                     &stream.input,
-                    &stream.sender,
+                    &mut stream.sender,
                     stream.request_id.as_ref(),
                 )
                 .then_some(*client_id)
@@ -355,13 +1129,57 @@ fn process_remote_requests(world: &mut World) {
             .collect::<Vec<_>>();
 
         for client_id in to_remove {
-            streams.remove(&client_id);
+            let Some(stream) = streams.remove(&client_id) else {
+                continue;
+            };
+
+            if let Some(on_disconnect) = stream.on_disconnect {
+                let _ = world.run_system_with_input(on_disconnect, &stream.input);
+            }
+            world.send_event(StreamServerEvent::ClientDisconnected(client_id));
         }
     });
 }
 
 fn on_app_exit(mut active_streams: ResMut<ActiveStreams>) {
-    active_streams.clear();
+    for (_, stream) in active_streams.drain() {
+        stream.sender.flush();
+    }
+}
+
+/// Run `request` with bevy's [`RemoteMethods`].
+/// Returns `None` when forwarding is disabled or the method is not registered there.
+fn run_remote_method(world: &mut World, request: &BrpRequest) -> Option<BrpResult> {
+    if !world.contains_resource::<ForwardRemoteMethods>() {
+        return None;
+    }
+
+    let system_id = world
+        .get_resource::<RemoteMethods>()?
+        .get(&request.method)?
+        .clone();
+
+    let result = match system_id {
+        RemoteMethodSystemId::Instant(system_id) => world
+            .run_system_with_input(system_id, request.params.clone())
+            .unwrap_or_else(|error| {
+                Err(BrpError {
+                    code: error_codes::INTERNAL_ERROR,
+                    message: format!("Failed to run method handler: {error}"),
+                    data: None,
+                })
+            }),
+        RemoteMethodSystemId::Watching(_) => Err(BrpError {
+            code: error_codes::METHOD_NOT_FOUND,
+            message: format!(
+                "Watching method `{}` is not supported over the stream server",
+                request.method
+            ),
+            data: None,
+        }),
+    };
+
+    Some(result)
 }
 
 #[must_use]
@@ -369,7 +1187,7 @@ fn run_handler(
     world: &mut World,
     system_id: StreamHandler,
     input: &StreamHandlerInput,
-    sender: &ActiveStreamSender,
+    sender: &mut ActiveStreamSender,
     request_id: Option<&Value>,
 ) -> bool {
     let result = world.run_system_with_input(system_id, &(input));
@@ -400,3 +1218,130 @@ fn run_handler(
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn update(_: StreamHandlerInputRef) -> Option<BrpResult> {
+        None
+    }
+
+    #[test]
+    fn test_backpressure_queue_overflow_resyncs() {
+        let (sender, receiver) = channel::bounded(1);
+        let mut sender = ActiveStreamSender::new(sender, StreamDelivery::Backpressure);
+
+        // One response fills the channel, the next ones fill the queue until it overflows
+        for _ in 0..MAX_PENDING_RESPONSES + 2 {
+            assert!(sender.send(None, Ok(Value::Null)));
+        }
+        assert!(sender.pending.is_empty());
+        assert!(matches!(sender.catch_up(), CatchUp::Behind));
+
+        while receiver.try_recv().is_ok() {}
+        assert!(matches!(sender.catch_up(), CatchUp::Resync));
+        assert!(matches!(sender.catch_up(), CatchUp::Ready));
+    }
+
+    #[derive(Resource, Default)]
+    struct Disconnected(Vec<StreamClientId>);
+
+    fn on_disconnect(InRef(input): StreamHandlerInputRef, mut disconnected: ResMut<Disconnected>) {
+        disconnected.0.push(input.client_id);
+    }
+
+    #[test]
+    fn test_lagged_stream_runs_on_disconnect() {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            RemoteStreamPlugin::default().with_method(
+                "test/stream",
+                RemoteStreamHandlersBuilder::new(update).on_disconnect(on_disconnect),
+            ),
+        ))
+        .init_resource::<Disconnected>();
+        app.update();
+
+        // A client that reads one response at a time
+        let (sender, receiver) = channel::bounded(1);
+        let client = app.world().resource::<StreamSender>().client();
+        let message = StreamMessage {
+            client_id: client.id,
+            kind: StreamMessageKind::Connect(
+                None,
+                BrpStreamMessage {
+                    method: "test/stream".into(),
+                    params: None,
+                    sender,
+                },
+            ),
+        };
+        client.sender.try_send(message).unwrap();
+        app.update();
+
+        let mut broadcasts = app.world_mut().resource_mut::<StreamBroadcasts>();
+        for _ in 0..MAX_PENDING_RESPONSES + 2 {
+            broadcasts.send_to_method("test/stream", Ok(Value::Null));
+        }
+        app.update();
+        assert!(app.world().resource::<Disconnected>().0.is_empty());
+
+        // Without `on_resync` the stream is closed once the client caught up
+        while receiver.try_recv().is_ok() {}
+        app.update();
+
+        assert_eq!(app.world().resource::<Disconnected>().0, vec![client.id]);
+    }
+
+    #[test]
+    fn test_min_interval_from_params() {
+        let method = Some(Duration::from_millis(10));
+        let rate = |hz: Value| min_interval(method, Some(&json!({ "max_update_rate": hz })));
+
+        assert_eq!(rate(json!(10.0)), Some(Duration::from_millis(100)));
+        // The method's interval is a floor
+        assert_eq!(rate(json!(1000.0)), method);
+        assert_eq!(rate(json!(1e300)), method);
+        // Invalid rates are ignored
+        assert_eq!(rate(json!(0.0)), method);
+        assert_eq!(rate(json!(-5.0)), method);
+        assert_eq!(rate(json!(1e-300)), method);
+        assert_eq!(rate(json!("fast")), method);
+        assert_eq!(min_interval(None, None), None);
+    }
+
+    #[test]
+    fn test_with_max_rate() {
+        let interval = |hz| {
+            RemoteStreamHandlersBuilder::new(update)
+                .with_max_rate(hz)
+                .min_interval
+        };
+
+        assert_eq!(interval(4.0), Some(Duration::from_millis(250)));
+        assert_eq!(interval(1e300), Some(Duration::ZERO));
+        assert_eq!(interval(1e-300), Some(Duration::MAX));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_with_max_rate_zero() {
+        RemoteStreamHandlersBuilder::new(update).with_max_rate(0.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_with_max_rate_negative() {
+        RemoteStreamHandlersBuilder::new(update).with_max_rate(-1.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_with_max_rate_nan() {
+        RemoteStreamHandlersBuilder::new(update).with_max_rate(f64::NAN);
+    }
+}
@@ -0,0 +1,182 @@
+//! Newline-delimited JSON-RPC shared by the socket transports.
+//!
+//! The first line sent by a client is the JSON-RPC request that opens the stream,
+//! every following line is passed to the stream as data.
+//! Each response is written back as a single line.
+//! A line longer than [`MAX_REQUEST_SIZE`] closes the connection.
+
+use bevy::{
+    remote::{BrpError, BrpRequest, BrpResponse},
+    tasks::IoTaskPool,
+};
+use serde_json::Value;
+use smol::{
+    channel::{self, Receiver},
+    io::{
+        self, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+        BufReader,
+    },
+};
+
+use crate::{error_codes, StreamClient, MAX_REQUEST_SIZE};
+
+pub(crate) async fn handle_client<S>(stream: S, mut client: StreamClient) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (read_stream, mut write_stream) = io::split(stream);
+    let mut reader = BufReader::new(read_stream);
+
+    let request = match read_line(&mut reader).await {
+        Ok(Some(line)) => parse_request(&line),
+        Ok(None) => return Ok(()),
+        Err(err) => Err(err),
+    };
+
+    let request = match request {
+        Ok(request) => request,
+        Err(err) => {
+            let response = BrpResponse::new(
+                None,
+                Err(BrpError {
+                    code: error_codes::INVALID_REQUEST,
+                    message: format!("{err}"),
+                    data: None,
+                }),
+            );
+            write_line(&mut write_stream, &response).await?;
+
+            return Ok(());
+        }
+    };
+
+    let (result_sender, result_receiver) = channel::bounded(32);
+
+    IoTaskPool::get()
+        .spawn(send_stream_response(write_stream, result_receiver))
+        .detach();
+
+    client.connect(request, result_sender.clone()).await?;
+
+    loop {
+        let line = match read_line(&mut reader).await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                // The error is the last response, the connection closes after it
+                let _ = result_sender
+                    .send(BrpResponse::new(
+                        None,
+                        Err(BrpError {
+                            code: error_codes::INVALID_REQUEST,
+                            message: format!("{err}"),
+                            data: None,
+                        }),
+                    ))
+                    .await;
+                break;
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<Value>(&line) {
+            Ok(msg) => client.send(msg).await?,
+            Err(err) => {
+                let _ = result_sender
+                    .send(BrpResponse::new(
+                        None,
+                        Err(BrpError {
+                            code: error_codes::PARSE_ERROR,
+                            message: format!("Failed to parse request: {err}"),
+                            data: None,
+                        }),
+                    ))
+                    .await;
+            }
+        }
+    }
+
+    client.disconnect().await?;
+
+    Ok(())
+}
+
+/// Read a line of at most [`MAX_REQUEST_SIZE`] bytes, `None` at the end of the stream.
+async fn read_line(reader: &mut (impl AsyncBufRead + Unpin)) -> anyhow::Result<Option<String>> {
+    let mut line = Vec::new();
+    // One more byte than allowed, to tell a line of the maximum size from a longer one
+    let read = reader
+        .take(MAX_REQUEST_SIZE as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if read == 0 {
+        return Ok(None);
+    }
+
+    if line.ends_with(b"\n") {
+        line.pop();
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+    }
+    if line.len() > MAX_REQUEST_SIZE {
+        anyhow::bail!("Request is larger than {MAX_REQUEST_SIZE} bytes");
+    }
+
+    Ok(Some(String::from_utf8(line)?))
+}
+
+fn parse_request(line: &str) -> anyhow::Result<BrpRequest> {
+    let request = serde_json::from_str::<BrpRequest>(line)?;
+
+    if request.jsonrpc != "2.0" {
+        anyhow::bail!("JSON-RPC request requires `\"jsonrpc\": \"2.0\"`")
+    }
+
+    Ok(request)
+}
+
+async fn write_line(
+    stream: &mut (impl AsyncWrite + Unpin),
+    response: &BrpResponse,
+) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(response)?;
+    line.push(b'\n');
+    stream.write_all(&line).await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+async fn send_stream_response(
+    mut stream: impl AsyncWrite + Unpin,
+    result_receiver: Receiver<BrpResponse>,
+) -> anyhow::Result<()> {
+    while let Ok(response) = result_receiver.recv().await {
+        write_line(&mut stream, &response).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_line_limit() {
+        smol::block_on(async {
+            let longest = "a".repeat(MAX_REQUEST_SIZE);
+            let input = format!("{{}}\r\n{longest}\n{longest}a\n");
+            let mut reader = input.as_bytes();
+
+            assert_eq!(read_line(&mut reader).await.unwrap().as_deref(), Some("{}"));
+            assert_eq!(read_line(&mut reader).await.unwrap(), Some(longest));
+            assert!(read_line(&mut reader).await.is_err());
+            assert!(read_line(&mut &b""[..]).await.unwrap().is_none());
+        });
+    }
+}
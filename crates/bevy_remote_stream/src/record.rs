@@ -0,0 +1,533 @@
+//! Record the responses sent by the WebSocket server to a JSONL file, and replay them later
+//! without the app running.
+//!
+//! Every line of a recording is a [`RecordedResponse`]. A recording is started with
+//! [`RemoteStreamWebSocketPlugin::with_recording`](crate::websocket::RemoteStreamWebSocketPlugin::with_recording)
+//! and served back with [`StreamReplay`], e.g. for the web inspector to browse a session attached
+//! to a bug report.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    net::{IpAddr, TcpListener, TcpStream},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use bevy::{
+    prelude::*,
+    remote::{BrpError, BrpResponse},
+    tasks::{IoTaskPool, TaskPool},
+};
+use futures_util::{SinkExt, StreamExt};
+use http_body_util::Full;
+use hyper::{
+    body::{Bytes, Incoming},
+    header::ORIGIN,
+    server::conn::http1,
+    service, Request, Response,
+};
+use hyper_tungstenite::HyperWebsocket;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use smol::{
+    channel::{self, Receiver, Sender},
+    io::AsyncWriteExt,
+    Async, Timer, Unblock,
+};
+use smol_hyper::rt::{FuturesIo, SmolTimer};
+use tungstenite::Message;
+
+use crate::{
+    error_codes,
+    subscription::TaggedResponse,
+    websocket::{is_origin_allowed, query_param, DEFAULT_ALLOWED_ORIGINS, QUERY_KEY},
+};
+
+/// A line of a recording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    /// Milliseconds since the recording started.
+    pub time_ms: u64,
+    /// The WebSocket connection the response was sent on, numbered from 1.
+    pub connection: u64,
+    /// The response, as sent to the client.
+    pub response: Value,
+}
+
+/// Writes the responses of every WebSocket connection to a recording.
+#[derive(Clone)]
+pub(crate) struct StreamRecorder(Arc<RecorderState>);
+
+struct RecorderState {
+    /// The lines to append to the recording, written by a single task.
+    lines: Sender<String>,
+    started_at: Instant,
+    connections: AtomicU64,
+}
+
+impl StreamRecorder {
+    pub(crate) fn create(path: &Path) -> io::Result<Self> {
+        let file = File::create(path)?;
+        let (lines, receiver) = channel::unbounded();
+        task_pool()
+            .spawn(write_lines(Unblock::new(file), receiver))
+            .detach();
+
+        Ok(Self(Arc::new(RecorderState {
+            lines,
+            started_at: Instant::now(),
+            connections: AtomicU64::new(0),
+        })))
+    }
+
+    /// Start recording a new connection.
+    pub(crate) fn connection(&self) -> ConnectionRecorder {
+        ConnectionRecorder {
+            recorder: self.clone(),
+            connection: self.0.connections.fetch_add(1, Ordering::Relaxed) + 1,
+        }
+    }
+}
+
+pub(crate) struct ConnectionRecorder {
+    recorder: StreamRecorder,
+    connection: u64,
+}
+
+impl ConnectionRecorder {
+    pub(crate) fn record(&self, response: &TaggedResponse) {
+        if let Err(err) = self.write(response) {
+            warn!("Failed to record a stream response: {err}");
+        }
+    }
+
+    fn write(&self, response: &TaggedResponse) -> anyhow::Result<()> {
+        let state = &self.recorder.0;
+        let mut line = serde_json::to_string(&RecordedResponse {
+            time_ms: state.started_at.elapsed().as_millis() as u64,
+            connection: self.connection,
+            response: serde_json::to_value(response)?,
+        })?;
+        line.push('\n');
+
+        state
+            .lines
+            .try_send(line)
+            .map_err(|_| anyhow::anyhow!("The recording was closed"))
+    }
+}
+
+/// The pool of the app, created when recordings are replayed without one.
+fn task_pool() -> &'static IoTaskPool {
+    IoTaskPool::get_or_init(TaskPool::new)
+}
+
+/// Append the recorded lines to the file, so the connections never wait on the disk.
+async fn write_lines(mut file: Unblock<File>, lines: Receiver<String>) {
+    while let Ok(line) = lines.recv().await {
+        // Flush every line, so the recording is usable even if the app crashes
+        let written = async {
+            file.write_all(line.as_bytes()).await?;
+            file.flush().await
+        }
+        .await;
+
+        if let Err(err) = written {
+            warn!("Failed to record a stream response: {err}");
+        }
+    }
+}
+
+/// Serves a recording over WebSocket, like the server it was recorded from.
+///
+/// Every client that connects receives the responses of one recorded connection, with their
+/// original timing. Requests sent by clients are answered with an error, as there is no app to
+/// handle them. Only JSON is supported.
+pub struct StreamReplay {
+    responses: Vec<RecordedResponse>,
+    connection: Option<u64>,
+    allowed_origins: Vec<String>,
+}
+
+impl StreamReplay {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open recording {}", path.display()))?;
+
+        let mut responses = vec![];
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = serde_json::from_str(&line)
+                .with_context(|| format!("Invalid recording at line {}", index + 1))?;
+            responses.push(response);
+        }
+
+        Ok(Self {
+            responses,
+            connection: None,
+            allowed_origins: DEFAULT_ALLOWED_ORIGINS
+                .iter()
+                .map(|origin| origin.to_string())
+                .collect(),
+        })
+    }
+
+    /// The recorded connections, in the order they were opened.
+    pub fn connections(&self) -> Vec<u64> {
+        let mut connections: Vec<u64> = vec![];
+        for response in &self.responses {
+            if !connections.contains(&response.connection) {
+                connections.push(response.connection);
+            }
+        }
+
+        connections
+    }
+
+    /// Replay `connection` instead of the first recorded connection.
+    #[must_use]
+    pub fn with_connection(mut self, connection: u64) -> Self {
+        self.connection = Some(connection);
+        self
+    }
+
+    /// Also allow web pages from `origin` to connect, see
+    /// [`RemoteStreamWebSocketPlugin::with_allowed_origins`](crate::websocket::RemoteStreamWebSocketPlugin::with_allowed_origins).
+    #[must_use]
+    pub fn with_allowed_origin(mut self, origin: impl Into<String>) -> Self {
+        self.allowed_origins.push(origin.into());
+        self
+    }
+
+    /// Serve the recording on `address` and `port` until the process exits.
+    pub fn serve(self, address: IpAddr, port: u16) -> anyhow::Result<()> {
+        let session = Arc::new(self.session()?);
+
+        smol::block_on(async {
+            let listener = Async::<TcpListener>::bind((address, port))?;
+            serve_session(listener, session).await
+        })
+    }
+
+    /// Keep the responses of the replayed connection.
+    fn session(self) -> anyhow::Result<ReplaySession> {
+        let connection = self
+            .connection
+            .or_else(|| self.connections().first().copied())
+            .context("The recording is empty")?;
+
+        let mut responses = self
+            .responses
+            .into_iter()
+            .filter(|response| response.connection == connection)
+            .peekable();
+        let started_at = responses
+            .peek()
+            .map(|response| response.time_ms)
+            .with_context(|| format!("Connection {connection} is not in the recording"))?;
+
+        Ok(ReplaySession {
+            responses: responses
+                .map(|response| {
+                    (
+                        // Saturate, a hand edited recording may be out of order
+                        Duration::from_millis(response.time_ms.saturating_sub(started_at)),
+                        response.response,
+                    )
+                })
+                .collect(),
+            allowed_origins: self.allowed_origins,
+        })
+    }
+}
+
+async fn serve_session(
+    listener: Async<TcpListener>,
+    session: Arc<ReplaySession>,
+) -> anyhow::Result<()> {
+    loop {
+        let (client, _) = listener.accept().await?;
+        let session = session.clone();
+
+        task_pool()
+            .spawn(async move {
+                if let Err(err) = replay_client(client, session).await {
+                    warn!("Replay connection failed: {err}");
+                }
+            })
+            .detach();
+    }
+}
+
+struct ReplaySession {
+    /// The responses to send, with their time since the connection was opened.
+    responses: Vec<(Duration, Value)>,
+    allowed_origins: Vec<String>,
+}
+
+async fn replay_client(
+    stream: Async<TcpStream>,
+    session: Arc<ReplaySession>,
+) -> anyhow::Result<()> {
+    http1::Builder::new()
+        .timer(SmolTimer::new())
+        .serve_connection(
+            FuturesIo::new(stream),
+            service::service_fn(|request| replay_request(request, session.clone())),
+        )
+        .with_upgrades()
+        .await?;
+
+    Ok(())
+}
+
+async fn replay_request(
+    mut request: Request<Incoming>,
+    session: Arc<ReplaySession>,
+) -> anyhow::Result<Response<Full<Bytes>>> {
+    if let Some(origin) = request.headers().get(ORIGIN) {
+        if !is_origin_allowed(&session.allowed_origins, origin) {
+            return Ok(Response::builder()
+                .status(403)
+                .body(Full::new(Bytes::from_static(b"Origin is not allowed")))?);
+        }
+    }
+
+    if !hyper_tungstenite::is_upgrade_request(&request) {
+        return Ok(Response::builder()
+            .status(400)
+            .body(Full::new(Bytes::from_static(
+                b"Only WebSocket connections are supported",
+            )))?);
+    }
+
+    let opened = query_param(&request, QUERY_KEY).is_some();
+    let (response, ws) = hyper_tungstenite::upgrade(&mut request, None)?;
+
+    task_pool()
+        .spawn(async move {
+            if let Err(err) = replay_websocket(ws, session, opened).await {
+                warn!("Replay connection failed: {err}");
+            }
+        })
+        .detach();
+
+    Ok(response)
+}
+
+/// `opened` is whether the upgrade request opened the stream, otherwise the first message
+/// does and the recording answers it.
+async fn replay_websocket(
+    ws: HyperWebsocket,
+    session: Arc<ReplaySession>,
+    mut opened: bool,
+) -> anyhow::Result<()> {
+    let (mut write, mut read) = ws.await?.split();
+    let started_at = Instant::now();
+    let mut responses = session.responses.iter();
+    let mut next = responses.next();
+
+    loop {
+        let due = next.map(|(time, _)| started_at + *time);
+        let message = smol::future::or(
+            async {
+                match due {
+                    Some(due) => Timer::at(due).await,
+                    None => Timer::never().await,
+                };
+                None
+            },
+            async { Some(read.next().await) },
+        )
+        .await;
+
+        match message {
+            // The next response is due
+            None => {
+                let Some((_, response)) = next else {
+                    continue;
+                };
+                write
+                    .send(Message::text(serde_json::to_string(response)?))
+                    .await?;
+                next = responses.next();
+            }
+            Some(Some(Ok(Message::Text(text)))) => {
+                if !opened {
+                    opened = true;
+                    continue;
+                }
+
+                let Some(id) = serde_json::from_str::<Value>(&text)
+                    .ok()
+                    .and_then(|request| request.get("id").cloned())
+                else {
+                    continue;
+                };
+
+                let response = BrpResponse::new(
+                    Some(id),
+                    Err(BrpError {
+                        code: error_codes::INVALID_REQUEST,
+                        message: "Requests are not supported while replaying a recording".into(),
+                        data: None,
+                    }),
+                );
+                write
+                    .send(Message::text(serde_json::to_string(&response)?))
+                    .await?;
+            }
+            Some(Some(Ok(Message::Close(_)) | Err(_)) | None) => return Ok(()),
+            Some(Some(Ok(_))) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::websocket::DEFAULT_ADDR;
+
+    fn response(id: u64, result: Value) -> TaggedResponse {
+        TaggedResponse {
+            response: BrpResponse::new(Some(Value::from(id)), Ok(result)),
+            subscription: None,
+        }
+    }
+
+    /// Record a response on a first connection and two on a second one.
+    fn record(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}-{}.jsonl", std::process::id()));
+        let recorder = StreamRecorder::create(&path).unwrap();
+        let first = recorder.connection();
+        let second = recorder.connection();
+        first.record(&response(1, json!("first")));
+        second.record(&response(1, json!("second")));
+        second.record(&response(2, json!("second again")));
+        drop((recorder, first, second));
+
+        // The lines are written by a task, wait for all of them
+        let started_at = Instant::now();
+        while !fs::read_to_string(&path)
+            .is_ok_and(|recording| recording.lines().count() == 3 && recording.ends_with('\n'))
+        {
+            assert!(
+                started_at.elapsed() < Duration::from_secs(5),
+                "Timed out waiting for the recording"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        path
+    }
+
+    fn results(session: &ReplaySession) -> Vec<&Value> {
+        session
+            .responses
+            .iter()
+            .map(|(_, response)| &response["result"])
+            .collect()
+    }
+
+    #[test]
+    fn test_record_and_load() {
+        let path = record("test_record_and_load");
+
+        let replay = StreamReplay::load(&path).unwrap();
+        assert_eq!(replay.connections(), vec![1, 2]);
+        let session = replay.session().unwrap();
+        assert_eq!(results(&session), vec![&json!("first")]);
+
+        let session = StreamReplay::load(&path)
+            .unwrap()
+            .with_connection(2)
+            .session()
+            .unwrap();
+        assert_eq!(
+            results(&session),
+            vec![&json!("second"), &json!("second again")]
+        );
+
+        let replay = StreamReplay::load(&path).unwrap().with_connection(3);
+        assert!(replay.session().is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_out_of_order_recording() {
+        let replay = StreamReplay {
+            responses: [10, 5, 20]
+                .map(|time_ms| RecordedResponse {
+                    time_ms,
+                    connection: 1,
+                    response: Value::Null,
+                })
+                .into(),
+            connection: None,
+            allowed_origins: vec![],
+        };
+
+        let times: Vec<_> = replay
+            .session()
+            .unwrap()
+            .responses
+            .iter()
+            .map(|(time, _)| *time)
+            .collect();
+        assert_eq!(times, [0, 0, 10].map(Duration::from_millis),);
+    }
+
+    #[test]
+    fn test_replay_rejects_requests() {
+        let path = record("test_replay_rejects_requests");
+        let session = StreamReplay::load(&path).unwrap().session().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let listener = Async::<TcpListener>::bind((DEFAULT_ADDR, 0)).unwrap();
+        let port = listener.get_ref().local_addr().unwrap().port();
+        task_pool()
+            .spawn(serve_session(listener, Arc::new(session)))
+            .detach();
+
+        let (mut client, _) = tungstenite::connect(format!("ws://{DEFAULT_ADDR}:{port}")).unwrap();
+        for (id, method) in [(1, "test/stream"), (2, "test/request")] {
+            let request = json!({ "jsonrpc": "2.0", "method": method, "id": id });
+            client.send(Message::text(request.to_string())).unwrap();
+        }
+
+        // The recorded response answers the opening request, the other one is rejected
+        let mut responses = vec![];
+        while responses.len() < 2 {
+            if let Message::Text(text) = client.read().unwrap() {
+                responses.push(serde_json::from_str::<Value>(&text).unwrap());
+            }
+        }
+        let rejected = responses
+            .iter()
+            .find(|response| response["id"] == json!(2))
+            .unwrap();
+        assert_eq!(
+            rejected["error"]["code"],
+            json!(error_codes::INVALID_REQUEST)
+        );
+        assert!(responses
+            .iter()
+            .any(|response| response["result"] == json!("first")));
+    }
+}
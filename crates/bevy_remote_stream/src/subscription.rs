@@ -0,0 +1,488 @@
+//! Several streams over a single connection.
+//!
+//! - `stream/subscribe` with `{ "method": <stream method>, "params": <connect params> }` opens a
+//!   new stream. The reply is `{ "subscription": <id> }`, every response of the new stream
+//!   has the id of the subscribe request and a `"subscription": <id>` field. When the stream
+//!   can't be opened (e.g. unknown method) or ends, its last response is the error that closed it
+//!   and the subscription is removed.
+//! - Requests with a `"subscription": <id>` field are sent to that stream.
+//! - `stream/unsubscribe` with `{ "subscription": <id> }` closes the stream.
+//!
+//! Everything else goes to the stream opened by the connection itself.
+
+use bevy::{
+    remote::{BrpError, BrpRequest, BrpResponse, BrpResult},
+    tasks::IoTaskPool,
+    utils::HashMap,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use smol::{
+    channel::{self, Receiver, Sender},
+    lock::SemaphoreGuardArc,
+};
+
+use crate::{error_codes, websocket::AdmissionGate, StreamClient, StreamClientId, StreamSender};
+
+pub const SUBSCRIBE_METHOD: &str = "stream/subscribe";
+pub const UNSUBSCRIBE_METHOD: &str = "stream/unsubscribe";
+const SUBSCRIPTION_KEY: &str = "subscription";
+
+/// A response, tagged with the subscription it belongs to.
+#[derive(Serialize)]
+pub struct TaggedResponse {
+    #[serde(flatten)]
+    pub response: BrpResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscription: Option<StreamClientId>,
+}
+
+#[derive(Deserialize)]
+struct SubscribeParams {
+    method: String,
+    #[serde(default)]
+    params: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct UnsubscribeParams {
+    subscription: StreamClientId,
+}
+
+pub struct Subscriptions {
+    sender: StreamSender,
+    outgoing: Sender<TaggedResponse>,
+    /// Every subscription is admitted like a new client.
+    gate: AdmissionGate,
+    clients: HashMap<StreamClientId, Subscription>,
+    /// Subscriptions whose stream was closed by the app.
+    ended: (Sender<StreamClientId>, Receiver<StreamClientId>),
+}
+
+impl Subscriptions {
+    pub fn new(
+        sender: StreamSender,
+        outgoing: Sender<TaggedResponse>,
+        gate: AdmissionGate,
+    ) -> Self {
+        Self {
+            sender,
+            outgoing,
+            gate,
+            clients: HashMap::default(),
+            ended: channel::unbounded(),
+        }
+    }
+
+    /// Open the stream of the connection for `request`.
+    pub async fn connect(
+        &self,
+        client: &mut StreamClient,
+        request: BrpRequest,
+    ) -> anyhow::Result<()> {
+        self.open(client, request, None, None).await
+    }
+
+    /// Open a stream for `request`, its responses are tagged with `subscription`.
+    /// `reply` is sent before any of them.
+    async fn open(
+        &self,
+        client: &mut StreamClient,
+        request: BrpRequest,
+        subscription: Option<StreamClientId>,
+        reply: Option<BrpResponse>,
+    ) -> anyhow::Result<()> {
+        let (result_sender, result_receiver) = channel::bounded(32);
+
+        client.connect(request, result_sender).await?;
+
+        IoTaskPool::get()
+            .spawn(forward(
+                result_receiver,
+                subscription,
+                reply,
+                self.outgoing.clone(),
+                self.ended.0.clone(),
+            ))
+            .detach();
+
+        Ok(())
+    }
+
+    /// Wait for the stream of a subscription to end, and remove the subscription.
+    pub async fn remove_ended(&mut self) {
+        if let Ok(subscription) = self.ended.1.recv().await {
+            self.clients.remove(&subscription);
+        }
+    }
+
+    /// Handle a message of the client.
+    /// Returns the message back if it is meant for the stream opened by the connection.
+    pub async fn handle(&mut self, message: Value) -> anyhow::Result<Option<Value>> {
+        if let Some(subscription) = message.get(SUBSCRIPTION_KEY) {
+            let client = serde_json::from_value::<StreamClientId>(subscription.clone())
+                .ok()
+                .and_then(|id| self.clients.get(&id));
+
+            match client {
+                Some(subscription) => subscription.client.send(message).await?,
+                None => {
+                    self.reply(
+                        message.get("id").cloned(),
+                        Err(invalid_params("Unknown subscription")),
+                    )
+                    .await?
+                }
+            }
+
+            return Ok(None);
+        }
+
+        match message.get("method").and_then(Value::as_str) {
+            Some(SUBSCRIBE_METHOD) => self.subscribe(message).await?,
+            Some(UNSUBSCRIBE_METHOD) => self.unsubscribe(message).await?,
+            _ => return Ok(Some(message)),
+        }
+
+        Ok(None)
+    }
+
+    async fn subscribe(&mut self, message: Value) -> anyhow::Result<()> {
+        let request = match parse_request(message) {
+            Ok(request) => request,
+            Err((id, err)) => return self.reply(id, Err(err)).await,
+        };
+        let params = match parse_params::<SubscribeParams>(request.params) {
+            Ok(params) => params,
+            Err(err) => return self.reply(request.id, Err(err)).await,
+        };
+
+        let mut client = self.sender.client();
+        let subscription = client.id();
+        // Sent by the stream before its own responses, so it always comes first
+        let reply = BrpResponse::new(
+            request.id.clone(),
+            Ok(json!({ "subscription": subscription })),
+        );
+
+        let request = BrpRequest {
+            jsonrpc: request.jsonrpc,
+            method: params.method,
+            id: request.id,
+            params: params.params,
+        };
+        // Subscriptions are not queued, waiting would block the whole connection
+        let slot = match self.gate.admit(&request, false).await {
+            Ok(slot) => slot,
+            Err(err) => return self.reply(request.id, Err(err)).await,
+        };
+
+        self.open(&mut client, request, Some(subscription), Some(reply))
+            .await?;
+        self.clients.insert(
+            subscription,
+            Subscription {
+                client,
+                _slot: slot,
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn unsubscribe(&mut self, message: Value) -> anyhow::Result<()> {
+        let request = match parse_request(message) {
+            Ok(request) => request,
+            Err((id, err)) => return self.reply(id, Err(err)).await,
+        };
+        let params = match parse_params::<UnsubscribeParams>(request.params) {
+            Ok(params) => params,
+            Err(err) => return self.reply(request.id, Err(err)).await,
+        };
+
+        let Some(subscription) = self.clients.remove(&params.subscription) else {
+            return self
+                .reply(request.id, Err(invalid_params("Unknown subscription")))
+                .await;
+        };
+
+        subscription.client.disconnect().await?;
+        self.reply(request.id, Ok(Value::Null)).await
+    }
+
+    /// Answer the client outside of any stream.
+    pub async fn reply(&self, id: Option<Value>, result: BrpResult) -> anyhow::Result<()> {
+        self.outgoing
+            .send(TaggedResponse {
+                response: BrpResponse::new(id, result),
+                subscription: None,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Close every subscription.
+    pub async fn close(mut self) -> anyhow::Result<()> {
+        for (_, subscription) in self.clients.drain() {
+            subscription.client.disconnect().await?;
+        }
+
+        Ok(())
+    }
+}
+
+struct Subscription {
+    client: StreamClient,
+    /// The client slot taken by the subscription, released when it is closed.
+    _slot: Option<SemaphoreGuardArc>,
+}
+
+/// Parse a control message, returns its id with the error so the client can be answered.
+fn parse_request(message: Value) -> Result<BrpRequest, (Option<Value>, BrpError)> {
+    let id = message.get("id").cloned();
+
+    serde_json::from_value(message).map_err(|err| {
+        let error = BrpError {
+            code: error_codes::INVALID_REQUEST,
+            message: format!("Failed to parse request: {err}"),
+            data: None,
+        };
+
+        (id, error)
+    })
+}
+
+fn parse_params<T: DeserializeOwned>(params: Option<Value>) -> Result<T, BrpError> {
+    let params = params.ok_or_else(|| invalid_params("Missing params"))?;
+
+    serde_json::from_value(params).map_err(|err| invalid_params(&err.to_string()))
+}
+
+fn invalid_params(message: &str) -> BrpError {
+    BrpError {
+        code: error_codes::INVALID_PARAMS,
+        message: message.into(),
+        data: None,
+    }
+}
+
+async fn forward(
+    result_receiver: Receiver<BrpResponse>,
+    subscription: Option<StreamClientId>,
+    reply: Option<BrpResponse>,
+    outgoing: Sender<TaggedResponse>,
+    ended: Sender<StreamClientId>,
+) {
+    if let Some(reply) = reply {
+        let reply = TaggedResponse {
+            response: reply,
+            subscription: None,
+        };
+
+        if outgoing.send(reply).await.is_err() {
+            return;
+        }
+    }
+
+    while let Ok(response) = result_receiver.recv().await {
+        let response = TaggedResponse {
+            response,
+            subscription,
+        };
+
+        if outgoing.send(response).await.is_err() {
+            return;
+        }
+    }
+
+    if let Some(subscription) = subscription {
+        let _ = ended.try_send(subscription);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{TcpListener, TcpStream},
+        time::{Duration, Instant},
+    };
+
+    use bevy::{prelude::*, utils::HashSet};
+    use tungstenite::{Message, WebSocket};
+
+    use super::*;
+    use crate::{
+        websocket::{RemoteStreamWebSocketPlugin, DEFAULT_ADDR},
+        RemoteStreamHandlersBuilder, RemoteStreamPlugin, StreamHandlerInputRef,
+    };
+
+    /// Send the params of each client once.
+    fn update(
+        InRef(input): StreamHandlerInputRef,
+        mut sent: Local<HashSet<StreamClientId>>,
+    ) -> Option<BrpResult> {
+        sent.insert(input.client_id)
+            .then(|| Ok(json!({ "params": input.params })))
+    }
+
+    /// Start the server and open a connection with a stream of `test/stream`.
+    fn connect() -> (App, WebSocket<TcpStream>) {
+        let port = TcpListener::bind((DEFAULT_ADDR, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            RemoteStreamPlugin::default()
+                .with_method("test/stream", RemoteStreamHandlersBuilder::new(update)),
+            RemoteStreamWebSocketPlugin::default().with_port(port),
+        ));
+
+        // Wait for the server to listen
+        let started_at = Instant::now();
+        let stream = loop {
+            app.update();
+            if let Ok(stream) = TcpStream::connect((DEFAULT_ADDR, port)) {
+                break stream;
+            }
+            assert!(started_at.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        };
+
+        let (mut ws, _) =
+            tungstenite::client(format!("ws://{DEFAULT_ADDR}:{port}"), stream).unwrap();
+        ws.get_ref()
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        send(
+            &mut ws,
+            json!({ "jsonrpc": "2.0", "method": "test/stream", "id": 0, "params": "main" }),
+        );
+
+        (app, ws)
+    }
+
+    fn send(ws: &mut WebSocket<TcpStream>, message: Value) {
+        ws.send(Message::text(message.to_string())).unwrap();
+    }
+
+    /// Update the app until the client received `count` responses.
+    fn read(app: &mut App, ws: &mut WebSocket<TcpStream>, count: usize) -> Vec<Value> {
+        let started_at = Instant::now();
+        let mut responses = vec![];
+
+        while responses.len() < count {
+            assert!(
+                started_at.elapsed() < Duration::from_secs(5),
+                "Timed out waiting for responses, got {responses:?}"
+            );
+            app.update();
+
+            match ws.read() {
+                Ok(Message::Text(text)) => responses.push(serde_json::from_str(&text).unwrap()),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(err))
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) => {}
+                Err(err) => panic!("Connection failed: {err}"),
+            }
+        }
+
+        responses
+    }
+
+    #[test]
+    fn test_subscribe_and_unsubscribe() {
+        let (mut app, mut ws) = connect();
+        assert_eq!(
+            read(&mut app, &mut ws, 1)[0]["result"],
+            json!({ "params": "main" })
+        );
+
+        send(
+            &mut ws,
+            json!({
+                "jsonrpc": "2.0",
+                "method": SUBSCRIBE_METHOD,
+                "id": 1,
+                "params": { "method": "test/stream", "params": "sub" },
+            }),
+        );
+        let responses = read(&mut app, &mut ws, 2);
+
+        // The reply comes first, then the responses of the subscription
+        let subscription = responses[0]["result"]["subscription"].clone();
+        assert_eq!(responses[0]["id"], json!(1));
+        assert!(subscription.is_u64());
+        assert!(responses[0].get(SUBSCRIPTION_KEY).is_none());
+        assert_eq!(responses[1]["id"], json!(1));
+        assert_eq!(responses[1][SUBSCRIPTION_KEY], subscription);
+        assert_eq!(responses[1]["result"], json!({ "params": "sub" }));
+
+        send(
+            &mut ws,
+            json!({
+                "jsonrpc": "2.0",
+                "method": UNSUBSCRIBE_METHOD,
+                "id": 2,
+                "params": { "subscription": subscription },
+            }),
+        );
+        let responses = read(&mut app, &mut ws, 1);
+        assert_eq!(responses[0]["id"], json!(2));
+        assert_eq!(responses[0]["result"], Value::Null);
+    }
+
+    #[test]
+    fn test_unsubscribe_unknown() {
+        let (mut app, mut ws) = connect();
+        read(&mut app, &mut ws, 1);
+
+        send(
+            &mut ws,
+            json!({
+                "jsonrpc": "2.0",
+                "method": UNSUBSCRIBE_METHOD,
+                "id": 1,
+                "params": { "subscription": 12345 },
+            }),
+        );
+        let responses = read(&mut app, &mut ws, 1);
+        assert_eq!(responses[0]["id"], json!(1));
+        assert_eq!(
+            responses[0]["error"]["code"],
+            json!(error_codes::INVALID_PARAMS)
+        );
+    }
+
+    #[test]
+    fn test_malformed_message_keeps_connection() {
+        let (mut app, mut ws) = connect();
+        read(&mut app, &mut ws, 1);
+
+        ws.send(Message::text("not json")).unwrap();
+        let responses = read(&mut app, &mut ws, 1);
+        assert_eq!(
+            responses[0]["error"]["code"],
+            json!(error_codes::PARSE_ERROR)
+        );
+
+        // The connection is still usable
+        send(
+            &mut ws,
+            json!({ "jsonrpc": "2.0", "method": UNSUBSCRIBE_METHOD, "id": 1 }),
+        );
+        let responses = read(&mut app, &mut ws, 1);
+        assert_eq!(responses[0]["id"], json!(1));
+        assert_eq!(
+            responses[0]["error"]["code"],
+            json!(error_codes::INVALID_PARAMS)
+        );
+    }
+}
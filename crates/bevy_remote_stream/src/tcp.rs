@@ -0,0 +1,142 @@
+//! Newline-delimited JSON-RPC over a plain TCP socket.
+//!
+//! The first line sent by a client opens the stream,
+//! every following line is sent to it as a request.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use bevy::{prelude::*, tasks::IoTaskPool};
+use smol::Timer;
+
+use crate::{
+    bind_tcp, ndjson::handle_client, ServerAddress, StreamSender, StreamServerEvent,
+    ACCEPT_RETRY_DELAY,
+};
+
+/// The default port that the TCP server will listen on.
+pub const DEFAULT_PORT: u16 = 3001;
+
+/// The default host address that TCP server will use.
+pub const DEFAULT_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+
+/// Add this plugin to your [`App`] to allow remote connections over newline-delimited JSON-RPC.
+///
+/// The defaults are:
+/// - [`DEFAULT_ADDR`] : 127.0.0.1.
+/// - [`DEFAULT_PORT`] : 3001.
+///
+pub struct RemoteStreamTcpPlugin {
+    /// The address that the TCP server will use.
+    address: IpAddr,
+
+    /// The port that the TCP server will listen on.
+    port: u16,
+
+    /// How many of the following ports to try when `port` is already in use.
+    port_fallback: u16,
+}
+
+impl RemoteStreamTcpPlugin {
+    /// Set the IP address that the server will use.
+    #[must_use]
+    pub fn with_address(mut self, address: impl Into<IpAddr>) -> Self {
+        self.address = address.into();
+        self
+    }
+
+    /// Set the remote port that the server will listen on.
+    #[must_use]
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// When the port is already in use, try up to `attempts` following ports instead of failing.
+    /// The port actually used is reported with [`StreamServerEvent::Listening`].
+    #[must_use]
+    pub fn with_port_fallback(mut self, attempts: u16) -> Self {
+        self.port_fallback = attempts;
+        self
+    }
+}
+
+impl Default for RemoteStreamTcpPlugin {
+    fn default() -> Self {
+        Self {
+            address: DEFAULT_ADDR,
+            port: DEFAULT_PORT,
+            port_fallback: 0,
+        }
+    }
+}
+
+impl Plugin for RemoteStreamTcpPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TcpHostAddress(self.address))
+            .insert_resource(TcpHostPort(self.port))
+            .insert_resource(TcpPortFallback(self.port_fallback))
+            .add_systems(Startup, start_server);
+    }
+}
+
+#[derive(Debug, Resource)]
+pub struct TcpHostAddress(pub IpAddr);
+
+#[derive(Debug, Resource, Reflect)]
+pub struct TcpHostPort(pub u16);
+
+#[derive(Debug, Resource)]
+struct TcpPortFallback(u16);
+
+fn start_server(
+    sender: Res<StreamSender>,
+    address: Res<TcpHostAddress>,
+    remote_port: Res<TcpHostPort>,
+    port_fallback: Res<TcpPortFallback>,
+) {
+    IoTaskPool::get()
+        .spawn(server_main(
+            address.0,
+            remote_port.0,
+            port_fallback.0,
+            sender.clone(),
+        ))
+        .detach();
+}
+
+async fn server_main(
+    address: IpAddr,
+    port: u16,
+    port_fallback: u16,
+    request_sender: StreamSender,
+) -> anyhow::Result<()> {
+    let listener = match bind_tcp(address, port, port_fallback) {
+        Ok(listener) => listener,
+        Err(error) => {
+            request_sender.report(StreamServerEvent::BindFailed {
+                address: SocketAddr::new(address, port).into(),
+                error: error.to_string(),
+            });
+            return Err(error.into());
+        }
+    };
+    let address = listener.get_ref().local_addr()?;
+    request_sender.report(StreamServerEvent::Listening(address.into()));
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                request_sender.report(StreamServerEvent::AcceptFailed {
+                    address: ServerAddress::Socket(address),
+                    error: error.to_string(),
+                });
+                Timer::after(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        IoTaskPool::get()
+            .spawn(handle_client(stream, request_sender.client()))
+            .detach();
+    }
+}
@@ -0,0 +1,261 @@
+//! An in-process client to test stream methods without opening a socket.
+//!
+//! ```ignore
+//! let mut app = App::new();
+//! app.add_plugins((
+//!     MinimalPlugins,
+//!     RemoteStreamPlugin::default().with_method("my/stream", RemoteStreamHandlersBuilder::new(update)),
+//! ));
+//! // Run the startup schedules, which set up the channels
+//! app.update();
+//!
+//! let mut client = TestStreamClient::connect(app.world(), "my/stream", None);
+//! app.update();
+//! let responses = client.responses();
+//! ```
+
+use bevy::{
+    prelude::*,
+    remote::{BrpRequest, BrpResponse},
+};
+use serde_json::Value;
+use smol::channel::{self, Receiver, TrySendError};
+
+use crate::{BrpStreamMessage, StreamClient, StreamMessage, StreamMessageKind, StreamSender};
+
+/// The id of the request that opens the stream, which its responses carry.
+pub const TEST_STREAM_REQUEST_ID: u64 = 0;
+
+/// A client connected to a stream method through the [`StreamSender`] of the app.
+///
+/// Messages are handled on the next `app.update()`. Responses are kept until read with
+/// [`Self::responses`], so the stream never lags. Dropping the client disconnects it.
+pub struct TestStreamClient {
+    client: StreamClient,
+    responses: Receiver<BrpResponse>,
+    next_id: u64,
+}
+
+impl TestStreamClient {
+    /// Open a stream of `method` with the given connect `params`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the app has not been updated once since adding
+    /// [`RemoteStreamPlugin`](crate::RemoteStreamPlugin), or if its channel is full.
+    pub fn connect(world: &World, method: impl Into<String>, params: Option<Value>) -> Self {
+        let sender = world
+            .get_resource::<StreamSender>()
+            .expect("StreamSender is inserted on startup, run `app.update()` once first");
+        let (responses_sender, responses) = channel::unbounded();
+        let mut client = sender.client();
+
+        let message = StreamMessage {
+            client_id: client.id,
+            kind: StreamMessageKind::Connect(
+                Some(Value::from(TEST_STREAM_REQUEST_ID)),
+                BrpStreamMessage {
+                    method: method.into(),
+                    params,
+                    sender: responses_sender,
+                },
+            ),
+        };
+        try_send(&client, message).expect("Failed to connect the test client");
+        client.connected = true;
+
+        Self {
+            client,
+            responses,
+            next_id: TEST_STREAM_REQUEST_ID + 1,
+        }
+    }
+
+    pub fn id(&self) -> crate::StreamClientId {
+        self.client.id
+    }
+
+    /// Send a request to the stream, returns the id its response will carry.
+    pub fn send(
+        &mut self,
+        method: impl Into<String>,
+        params: Option<Value>,
+    ) -> anyhow::Result<Value> {
+        let id = Value::from(self.next_id);
+        self.next_id += 1;
+
+        let request = BrpRequest {
+            jsonrpc: "2.0".into(),
+            method: method.into(),
+            id: Some(id.clone()),
+            params,
+        };
+        let message = StreamMessage {
+            client_id: self.client.id,
+            kind: StreamMessageKind::Data(serde_json::to_value(request)?),
+        };
+        try_send(&self.client, message)?;
+
+        Ok(id)
+    }
+
+    /// Take the responses received since the last call.
+    pub fn responses(&mut self) -> Vec<BrpResponse> {
+        std::iter::from_fn(|| self.responses.try_recv().ok()).collect()
+    }
+
+    /// Whether the app closed the stream, e.g. after an error or a disconnect.
+    pub fn is_closed(&self) -> bool {
+        self.responses.is_closed()
+    }
+
+    /// Close the stream, its `on_disconnect` handler runs on the next `app.update()`.
+    pub fn disconnect(self) {
+        // `StreamClient` sends the disconnect message when dropped
+        drop(self);
+    }
+}
+
+fn try_send(client: &StreamClient, message: StreamMessage) -> anyhow::Result<()> {
+    match client.sender.try_send(message) {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Full(_)) => {
+            anyhow::bail!("The stream channel is full, run `app.update()` to drain it")
+        }
+        Err(TrySendError::Closed(_)) => anyhow::bail!("The stream channel is closed"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::remote::{BrpPayload, BrpResult};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        OnDataHandlerInput, RemoteStreamHandlersBuilder, RemoteStreamPlugin, StreamBroadcasts,
+        StreamClientId, StreamHandlerInputRef,
+    };
+
+    #[derive(Resource, Default)]
+    struct Disconnected(Vec<StreamClientId>);
+
+    fn update(InRef(input): StreamHandlerInputRef, mut frames: Local<u32>) -> Option<BrpResult> {
+        *frames += 1;
+        Some(Ok(json!({ "frame": *frames, "params": input.params })))
+    }
+
+    fn on_data(In((_, request)): OnDataHandlerInput) -> Option<BrpResult> {
+        Some(Ok(json!({ "echo": request.params })))
+    }
+
+    fn on_disconnect(InRef(input): StreamHandlerInputRef, mut disconnected: ResMut<Disconnected>) {
+        disconnected.0.push(input.client_id);
+    }
+
+    fn result(response: &BrpResponse) -> Option<&Value> {
+        match &response.payload {
+            BrpPayload::Result(value) => Some(value),
+            BrpPayload::Error(_) => None,
+        }
+    }
+
+    fn create_app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            RemoteStreamPlugin::default().with_method(
+                "test/stream",
+                RemoteStreamHandlersBuilder::new(update)
+                    .on_data(on_data)
+                    .on_disconnect(on_disconnect),
+            ),
+        ))
+        .init_resource::<Disconnected>();
+        app.update();
+
+        app
+    }
+
+    #[test]
+    fn test_stream_updates() {
+        let mut app = create_app();
+        let mut client = TestStreamClient::connect(app.world(), "test/stream", Some(json!(1)));
+
+        app.update();
+        app.update();
+
+        let responses = client.responses();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].id, Some(json!(TEST_STREAM_REQUEST_ID)));
+        assert_eq!(
+            result(&responses[1]),
+            Some(&json!({ "frame": 2, "params": 1 }))
+        );
+    }
+
+    #[test]
+    fn test_stream_data() {
+        let mut app = create_app();
+        let mut client = TestStreamClient::connect(app.world(), "test/stream", None);
+        app.update();
+        client.responses();
+
+        let id = client.send("test/echo", Some(json!("hello"))).unwrap();
+        app.update();
+
+        let response = client
+            .responses()
+            .into_iter()
+            .find(|response| response.id.as_ref() == Some(&id))
+            .unwrap();
+        assert_eq!(result(&response), Some(&json!({ "echo": "hello" })));
+    }
+
+    #[test]
+    fn test_stream_disconnect() {
+        let mut app = create_app();
+        let client = TestStreamClient::connect(app.world(), "test/stream", None);
+        let id = client.id();
+        app.update();
+
+        client.disconnect();
+        app.update();
+
+        assert_eq!(app.world().resource::<Disconnected>().0, vec![id]);
+    }
+
+    #[test]
+    fn test_stream_broadcast() {
+        let mut app = create_app();
+        let mut first = TestStreamClient::connect(app.world(), "test/stream", None);
+        let mut second = TestStreamClient::connect(app.world(), "test/stream", None);
+        app.update();
+        first.responses();
+        second.responses();
+
+        let mut broadcasts = app.world_mut().resource_mut::<StreamBroadcasts>();
+        broadcasts.send_to_method("test/stream", Ok(json!("level loaded")));
+        broadcasts.send_to_client(second.id(), Ok(json!("only second")));
+        app.update();
+
+        let first = first.responses();
+        let second = second.responses();
+        assert_eq!(result(&first[0]), Some(&json!("level loaded")));
+        assert_eq!(result(&second[0]), Some(&json!("level loaded")));
+        assert_eq!(result(&second[1]), Some(&json!("only second")));
+        assert_eq!((first.len(), second.len()), (2, 3));
+    }
+
+    #[test]
+    fn test_unknown_method() {
+        let mut app = create_app();
+        let mut client = TestStreamClient::connect(app.world(), "test/unknown", None);
+        app.update();
+
+        let responses = client.responses();
+        assert_eq!(responses.len(), 1);
+        assert!(result(&responses[0]).is_none());
+        assert!(client.is_closed());
+    }
+}
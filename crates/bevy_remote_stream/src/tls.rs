@@ -0,0 +1,67 @@
+//! TLS support for the WebSocket server, to serve `wss://` to browsers on other hosts.
+
+use std::{fs, path::Path, sync::Arc};
+
+use futures_rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer},
+    rustls::{crypto::ring, ServerConfig},
+    TlsAcceptor,
+};
+
+/// The certificate and private key used by the server.
+#[derive(Clone)]
+pub struct TlsConfig(Arc<ServerConfig>);
+
+impl TlsConfig {
+    /// Use a PEM encoded certificate chain and private key.
+    pub fn from_pem(cert_chain: &[u8], private_key: &[u8]) -> anyhow::Result<Self> {
+        let cert_chain = rustls_pemfile::certs(&mut &*cert_chain).collect::<Result<Vec<_>, _>>()?;
+        let private_key = rustls_pemfile::private_key(&mut &*private_key)?
+            .ok_or_else(|| anyhow::anyhow!("No private key found"))?;
+
+        Self::from_der(cert_chain, private_key)
+    }
+
+    /// Read a PEM encoded certificate chain and private key from files.
+    pub fn from_pem_files(
+        cert_chain: impl AsRef<Path>,
+        private_key: impl AsRef<Path>,
+    ) -> anyhow::Result<Self> {
+        Self::from_pem(&fs::read(cert_chain)?, &fs::read(private_key)?)
+    }
+
+    /// Use a DER encoded certificate chain and private key.
+    pub fn from_der(
+        cert_chain: Vec<CertificateDer<'static>>,
+        private_key: PrivateKeyDer<'static>,
+    ) -> anyhow::Result<Self> {
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(cert_chain, private_key)?;
+
+        Ok(Self::from_server_config(Arc::new(config)))
+    }
+
+    /// Use a rustls [`ServerConfig`] as is.
+    pub fn from_server_config(config: Arc<ServerConfig>) -> Self {
+        Self(config)
+    }
+
+    /// Generate a self-signed certificate for development, valid for `subject_alt_names`
+    /// (e.g. `["localhost", "192.168.1.10"]`).
+    ///
+    /// Browsers do not trust it, open `https://<host>:<port>` once to accept the certificate
+    /// before connecting the inspector.
+    #[cfg(feature = "self-signed")]
+    pub fn self_signed(subject_alt_names: impl Into<Vec<String>>) -> anyhow::Result<Self> {
+        let certified = rcgen::generate_simple_self_signed(subject_alt_names)?;
+        let private_key = PrivateKeyDer::Pkcs8(certified.key_pair.serialize_der().into());
+
+        Self::from_der(vec![certified.cert.der().clone()], private_key)
+    }
+
+    pub(crate) fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.0.clone())
+    }
+}
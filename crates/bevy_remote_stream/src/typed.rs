@@ -0,0 +1,377 @@
+//! Stream handlers with connect params and data requests deserialized into user types.
+
+use std::{any::Any, marker::PhantomData};
+
+use bevy::{
+    prelude::*,
+    remote::{BrpError, BrpResult},
+    utils::Duration,
+};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::{
+    error_codes, OnDataHandlerInput, RemoteStreamHandlersBuilder, StreamClientId, StreamDelivery,
+    StreamHandlerInputRef,
+};
+
+/// The input of typed stream handlers, with the connect params of the client.
+pub struct TypedStreamInput<P> {
+    pub client_id: StreamClientId,
+    pub params: P,
+}
+
+pub type TypedStreamInputRef<'a, P> = InRef<'a, TypedStreamInput<P>>;
+
+/// The input of typed data handlers, with the request deserialized from
+/// `{ "method": <method>, "params": <params> }`, e.g. into an enum with
+/// `#[serde(tag = "method", content = "params")]`.
+pub type TypedDataInput<R> = In<(StreamClientId, R)>;
+
+/// Deserializes the connect params of a client, stored in [`crate::StreamHandlerInput`].
+pub type ParamsParser =
+    fn(StreamClientId, Option<&Value>) -> Result<Box<dyn Any + Send + Sync>, BrpError>;
+
+/// Like [`RemoteStreamHandlersBuilder`], with the connect params deserialized into `P`.
+///
+/// Clients with params that don't deserialize are refused with [`error_codes::INVALID_PARAMS`]
+/// before `on_connect` runs. Missing params deserialize from `null`, use an `Option` or a type
+/// with `#[serde(default)]` to make them optional.
+pub struct TypedStreamHandlersBuilder<P> {
+    builder: RemoteStreamHandlersBuilder,
+    marker: PhantomData<fn() -> P>,
+}
+
+impl<P: DeserializeOwned + Send + Sync + 'static> TypedStreamHandlersBuilder<P> {
+    pub fn new<M>(
+        update: impl IntoSystem<TypedStreamInputRef<'static, P>, Option<BrpResult>, M>,
+    ) -> Self {
+        let mut builder = RemoteStreamHandlersBuilder::from_boxed(typed_handler::<P, _, _>(
+            update,
+            missing_params,
+        ));
+        builder.parse_params = Some(parse_params::<P>);
+
+        Self {
+            builder,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn on_connect<M>(
+        mut self,
+        system: impl IntoSystem<TypedStreamInputRef<'static, P>, Option<BrpResult>, M>,
+    ) -> Self {
+        self.builder.on_connect = Some(typed_handler::<P, _, _>(system, missing_params));
+        self
+    }
+
+    pub fn on_disconnect<M>(
+        mut self,
+        system: impl IntoSystem<TypedStreamInputRef<'static, P>, (), M>,
+    ) -> Self {
+        self.builder.on_disconnect = Some(typed_handler::<P, _, _>(system, || {
+            warn!("Typed on_disconnect handler ran without typed params, skipping it");
+        }));
+        self
+    }
+
+    /// See [`RemoteStreamHandlersBuilder::on_resync`].
+    pub fn on_resync<M>(
+        mut self,
+        system: impl IntoSystem<TypedStreamInputRef<'static, P>, Option<BrpResult>, M>,
+    ) -> Self {
+        self.builder.on_resync = Some(typed_handler::<P, _, _>(system, missing_params));
+        self
+    }
+
+    /// Set the handler of requests sent to the stream, deserialized into `R`.
+    /// Requests that don't deserialize get an [`error_codes::INVALID_PARAMS`] response.
+    pub fn on_data<R: DeserializeOwned + 'static, M>(
+        mut self,
+        system: impl IntoSystem<TypedDataInput<R>, Option<BrpResult>, M>,
+    ) -> Self {
+        self.builder.on_data = Some(typed_data_handler(system));
+        self
+    }
+
+    /// See [`RemoteStreamHandlersBuilder::with_delivery`].
+    pub fn with_delivery(mut self, delivery: StreamDelivery) -> Self {
+        self.builder = self.builder.with_delivery(delivery);
+        self
+    }
+
+    /// See [`RemoteStreamHandlersBuilder::with_min_interval`].
+    pub fn with_min_interval(mut self, interval: Duration) -> Self {
+        self.builder = self.builder.with_min_interval(interval);
+        self
+    }
+
+    /// See [`RemoteStreamHandlersBuilder::with_max_rate`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hz` is not a positive finite number.
+    pub fn with_max_rate(mut self, hz: f64) -> Self {
+        self.builder = self.builder.with_max_rate(hz);
+        self
+    }
+}
+
+impl<P> From<TypedStreamHandlersBuilder<P>> for RemoteStreamHandlersBuilder {
+    fn from(typed: TypedStreamHandlersBuilder<P>) -> Self {
+        typed.builder
+    }
+}
+
+fn parse_params<P: DeserializeOwned + Send + Sync + 'static>(
+    client_id: StreamClientId,
+    params: Option<&Value>,
+) -> Result<Box<dyn Any + Send + Sync>, BrpError> {
+    let params = serde_json::from_value::<P>(params.cloned().unwrap_or_default())
+        .map_err(|err| invalid_params(format!("Invalid params: {err}")))?;
+
+    Ok(Box::new(TypedStreamInput { client_id, params }))
+}
+
+fn invalid_params(message: String) -> BrpError {
+    BrpError {
+        code: error_codes::INVALID_PARAMS,
+        message,
+        data: None,
+    }
+}
+
+/// The result of typed handlers run without typed params, e.g. with an input built by
+/// [`crate::StreamHandlerInput::new`].
+fn missing_params() -> Option<BrpResult> {
+    Some(Err(BrpError {
+        code: error_codes::INTERNAL_ERROR,
+        message: "The stream was not opened with typed params".into(),
+        data: None,
+    }))
+}
+
+/// Make sure the closure is generic over the lifetime of its input.
+fn stream_handler<F, Out>(f: F) -> F
+where
+    F: for<'a> FnMut(StreamHandlerInputRef<'a>, &mut World) -> Out,
+{
+    f
+}
+
+/// Wrap a typed handler into a handler of [`crate::StreamHandlerInput`].
+/// `missing` is returned instead of running it when the input has no typed params.
+fn typed_handler<P: Send + Sync + 'static, Out: 'static, M>(
+    system: impl IntoSystem<TypedStreamInputRef<'static, P>, Out, M>,
+    missing: fn() -> Out,
+) -> Box<dyn System<In = StreamHandlerInputRef<'static>, Out = Out>> {
+    let mut system = IntoSystem::into_system(system);
+    let mut initialized = false;
+
+    let handler = stream_handler(move |InRef(input), world: &mut World| {
+        if !initialized {
+            system.initialize(world);
+            initialized = true;
+        }
+
+        let Some(input) = input
+            .typed
+            .as_ref()
+            .and_then(|typed| typed.downcast_ref::<TypedStreamInput<P>>())
+        else {
+            return missing();
+        };
+
+        system.run(input, world)
+    });
+
+    Box::new(IntoSystem::into_system(handler))
+}
+
+/// Wrap a typed data handler into a handler of [`crate::BrpRequest`]s.
+fn typed_data_handler<R: DeserializeOwned + 'static, M>(
+    system: impl IntoSystem<TypedDataInput<R>, Option<BrpResult>, M>,
+) -> Box<dyn System<In = OnDataHandlerInput, Out = Option<BrpResult>>> {
+    let mut system = IntoSystem::into_system(system);
+    let mut initialized = false;
+
+    let handler = move |In((client_id, request)): OnDataHandlerInput, world: &mut World| {
+        if !initialized {
+            system.initialize(world);
+            initialized = true;
+        }
+
+        let data = json!({ "method": request.method, "params": request.params });
+        let data = match serde_json::from_value::<R>(data) {
+            Ok(data) => data,
+            Err(err) => return Some(Err(invalid_params(format!("Invalid request: {err}")))),
+        };
+
+        system.run((client_id, data), world)
+    };
+
+    Box::new(IntoSystem::into_system(handler))
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::remote::{BrpPayload, BrpResponse};
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{testing::TestStreamClient, RemoteStreamPlugin, StreamHandlerInput};
+
+    #[derive(Deserialize)]
+    struct Params {
+        name: String,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(tag = "method", content = "params")]
+    enum Request {
+        #[serde(rename = "test/echo")]
+        Echo(String),
+    }
+
+    #[derive(Resource, Default)]
+    struct Disconnected(Vec<String>);
+
+    fn update(InRef(input): TypedStreamInputRef<Params>) -> Option<BrpResult> {
+        Some(Ok(json!({ "update": input.params.name })))
+    }
+
+    fn on_connect(InRef(input): TypedStreamInputRef<Params>) -> Option<BrpResult> {
+        Some(Ok(json!({ "connect": input.params.name })))
+    }
+
+    fn on_disconnect(
+        InRef(input): TypedStreamInputRef<Params>,
+        mut disconnected: ResMut<Disconnected>,
+    ) {
+        disconnected.0.push(input.params.name.clone());
+    }
+
+    fn on_data(In((_, request)): TypedDataInput<Request>) -> Option<BrpResult> {
+        match request {
+            Request::Echo(text) => Some(Ok(json!({ "echo": text }))),
+        }
+    }
+
+    fn create_app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            RemoteStreamPlugin::default().with_method(
+                "test/stream",
+                TypedStreamHandlersBuilder::<Params>::new(update)
+                    .on_connect(on_connect)
+                    .on_disconnect(on_disconnect)
+                    .on_data(on_data),
+            ),
+        ))
+        .init_resource::<Disconnected>();
+        app.update();
+
+        app
+    }
+
+    fn result(response: &BrpResponse) -> Option<&Value> {
+        match &response.payload {
+            BrpPayload::Result(value) => Some(value),
+            BrpPayload::Error(_) => None,
+        }
+    }
+
+    fn error_code(response: &BrpResponse) -> Option<i16> {
+        match &response.payload {
+            BrpPayload::Result(_) => None,
+            BrpPayload::Error(err) => Some(err.code),
+        }
+    }
+
+    #[test]
+    fn test_params_reach_handlers() {
+        let mut app = create_app();
+        let mut client = TestStreamClient::connect(
+            app.world(),
+            "test/stream",
+            Some(json!({ "name": "player" })),
+        );
+        app.update();
+
+        let responses = client.responses();
+        let results: Vec<_> = responses.iter().filter_map(result).collect();
+        assert!(results.contains(&&json!({ "connect": "player" })));
+        assert!(results.contains(&&json!({ "update": "player" })));
+
+        client.disconnect();
+        app.update();
+
+        assert_eq!(app.world().resource::<Disconnected>().0, vec!["player"]);
+    }
+
+    #[test]
+    fn test_invalid_params_refused() {
+        let mut app = create_app();
+        let mut client =
+            TestStreamClient::connect(app.world(), "test/stream", Some(json!({ "name": 1 })));
+        app.update();
+
+        let responses = client.responses();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(error_code(&responses[0]), Some(error_codes::INVALID_PARAMS));
+        assert!(client.is_closed());
+    }
+
+    #[test]
+    fn test_invalid_data() {
+        let mut app = create_app();
+        let mut client = TestStreamClient::connect(
+            app.world(),
+            "test/stream",
+            Some(json!({ "name": "player" })),
+        );
+        app.update();
+        client.responses();
+
+        let valid = client.send("test/echo", Some(json!("hello"))).unwrap();
+        let invalid = client.send("test/echo", Some(json!(1))).unwrap();
+        app.update();
+
+        let responses = client.responses();
+        let response = |id: &Value| {
+            responses
+                .iter()
+                .find(|response| response.id.as_ref() == Some(id))
+                .unwrap()
+        };
+        assert_eq!(result(response(&valid)), Some(&json!({ "echo": "hello" })));
+        assert_eq!(
+            error_code(response(&invalid)),
+            Some(error_codes::INVALID_PARAMS)
+        );
+    }
+
+    #[test]
+    fn test_missing_typed_params() {
+        let mut world = World::new();
+        let update_id =
+            world.register_boxed_system(typed_handler::<Params, _, _>(update, missing_params));
+        let on_disconnect_id =
+            world.register_boxed_system(typed_handler::<Params, _, _>(on_disconnect, || {}));
+        world.init_resource::<Disconnected>();
+
+        let input = StreamHandlerInput::new(StreamClientId::default(), None);
+        let result = world.run_system_with_input(update_id, &input).unwrap();
+        assert_eq!(
+            result.unwrap().unwrap_err().code,
+            error_codes::INTERNAL_ERROR
+        );
+
+        world
+            .run_system_with_input(on_disconnect_id, &input)
+            .unwrap();
+        assert!(world.resource::<Disconnected>().0.is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+//! Newline-delimited JSON-RPC over a Unix domain socket.
+//!
+//! No port is opened, access to the socket is controlled by filesystem permissions.
+//! Clients speak the same line protocol as the TCP transport.
+
+use std::{
+    fs,
+    io::ErrorKind,
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+use bevy::{prelude::*, tasks::IoTaskPool};
+use smol::{Async, Timer};
+
+use crate::{
+    ndjson::handle_client, ServerAddress, StreamSender, StreamServerEvent, ACCEPT_RETRY_DELAY,
+};
+
+/// The default file name of the socket, created in `$XDG_RUNTIME_DIR`, see [`default_socket_path`].
+pub const DEFAULT_SOCKET_NAME: &str = "bevy_remote_stream.sock";
+
+/// The default permissions of the socket file, only the owner can connect.
+pub const DEFAULT_PERMISSIONS: u32 = 0o600;
+
+/// Add this plugin to your [`App`] to allow local connections through a Unix domain socket.
+///
+/// The defaults are:
+/// - path: [`default_socket_path`].
+/// - permissions: [`DEFAULT_PERMISSIONS`].
+///
+pub struct RemoteStreamUnixPlugin {
+    /// The path of the socket file.
+    path: PathBuf,
+
+    /// The permissions of the socket file, set before it is reachable at `path`.
+    permissions: u32,
+}
+
+impl RemoteStreamUnixPlugin {
+    /// Set the path of the socket file. Use distinct paths to run several apps side by side.
+    #[must_use]
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    /// Set the permissions of the socket file, e.g. `0o660` to allow the owner's group to connect.
+    #[must_use]
+    pub fn with_permissions(mut self, mode: u32) -> Self {
+        self.permissions = mode;
+        self
+    }
+}
+
+impl Default for RemoteStreamUnixPlugin {
+    fn default() -> Self {
+        Self {
+            path: default_socket_path(),
+            permissions: DEFAULT_PERMISSIONS,
+        }
+    }
+}
+
+/// [`DEFAULT_SOCKET_NAME`] in `$XDG_RUNTIME_DIR`, which only the user can access. Without it, a
+/// socket named after the process id in [`std::env::temp_dir`], which other users can list.
+pub fn default_socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(DEFAULT_SOCKET_NAME),
+        _ => std::env::temp_dir().join(format!("bevy_remote_stream-{}.sock", std::process::id())),
+    }
+}
+
+impl Plugin for RemoteStreamUnixPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(UnixSocketPath(self.path.clone()))
+            .insert_resource(UnixSocketPermissions(self.permissions))
+            .add_systems(Startup, start_server)
+            .add_systems(Last, remove_socket.run_if(on_event::<AppExit>));
+    }
+}
+
+#[derive(Debug, Resource)]
+pub struct UnixSocketPath(pub PathBuf);
+
+#[derive(Debug, Resource)]
+pub struct UnixSocketPermissions(pub u32);
+
+fn start_server(
+    sender: Res<StreamSender>,
+    path: Res<UnixSocketPath>,
+    permissions: Res<UnixSocketPermissions>,
+) {
+    let address = ServerAddress::Path(path.0.clone());
+    let listener = match bind(&path.0, permissions.0) {
+        Ok(listener) => listener,
+        Err(err) => {
+            sender.report(StreamServerEvent::BindFailed {
+                address,
+                error: err.to_string(),
+            });
+            return;
+        }
+    };
+    sender.report(StreamServerEvent::Listening(address.clone()));
+
+    IoTaskPool::get()
+        .spawn(server_main(listener, address, sender.clone()))
+        .detach();
+}
+
+fn bind(path: &Path, permissions: u32) -> anyhow::Result<Async<UnixListener>> {
+    // A socket left behind by a previous run would make `bind` fail
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            anyhow::bail!("File exists and is not a socket");
+        }
+        // Only remove the socket if no server is listening on it anymore
+        match UnixStream::connect(path) {
+            Ok(_) => anyhow::bail!("Socket is in use"),
+            Err(err) if err.kind() == ErrorKind::ConnectionRefused => fs::remove_file(path)?,
+            Err(err) => anyhow::bail!("Socket is in use: {err}"),
+        }
+    }
+
+    // Bind in a private directory and move the socket once its permissions are set,
+    // so it is never reachable with looser ones
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let staging = parent.join(format!(".bevy_remote_stream-{}", std::process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&staging)?;
+
+    let listener = bind_private(&staging.join(DEFAULT_SOCKET_NAME), path, permissions);
+    let _ = fs::remove_dir_all(&staging);
+
+    listener
+}
+
+fn bind_private(
+    staged: &Path,
+    path: &Path,
+    permissions: u32,
+) -> anyhow::Result<Async<UnixListener>> {
+    let listener = Async::<UnixListener>::bind(staged)?;
+    fs::set_permissions(staged, fs::Permissions::from_mode(permissions))?;
+    fs::rename(staged, path)?;
+
+    Ok(listener)
+}
+
+async fn server_main(
+    listener: Async<UnixListener>,
+    address: ServerAddress,
+    request_sender: StreamSender,
+) -> anyhow::Result<()> {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                request_sender.report(StreamServerEvent::AcceptFailed {
+                    address: address.clone(),
+                    error: error.to_string(),
+                });
+                Timer::after(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        IoTaskPool::get()
+            .spawn(handle_client(stream, request_sender.client()))
+            .detach();
+    }
+}
+
+fn remove_socket(path: Res<UnixSocketPath>) {
+    let _ = fs::remove_file(&path.0);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bind() {
+        let path = std::env::temp_dir().join(format!("test_bind-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let listener = bind(&path, 0o600).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        UnixStream::connect(&path).unwrap();
+
+        // A live socket is kept, a stale one is replaced
+        assert!(bind(&path, 0o600).is_err());
+        drop(listener);
+        let listener = bind(&path, 0o600).unwrap();
+        UnixStream::connect(&path).unwrap();
+
+        drop(listener);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,20 +1,26 @@
-use std::net::{IpAddr, Ipv4Addr, TcpListener, TcpStream};
+use std::{
+    borrow::Cow,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
 use bevy::{
     prelude::*,
-    remote::{error_codes, BrpError, BrpRequest, BrpResponse},
+    remote::{BrpError, BrpRequest, BrpResponse},
     tasks::IoTaskPool,
 };
 use futures_util::{
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
 };
-use http_body_util::Full;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
 use hyper::{
     body::{Bytes, Incoming},
     header::{
         HeaderValue, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
-        ACCESS_CONTROL_MAX_AGE, ORIGIN,
+        ACCESS_CONTROL_MAX_AGE, AUTHORIZATION, CONTENT_TYPE, ORIGIN,
     },
     server::conn::http1,
     service, Method, Request, Response,
@@ -23,18 +29,54 @@ use hyper_tungstenite::{HyperWebsocket, HyperWebsocketStream};
 use serde_json::Value;
 use smol::{
     channel::{self, Receiver, Sender},
-    Async,
+    io::{AsyncRead, AsyncWrite},
+    lock::{Semaphore, SemaphoreGuardArc},
+    Async, Timer,
 };
 use smol_hyper::rt::{FuturesIo, SmolTimer};
-use tungstenite::Message;
+use tungstenite::{
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Message,
+};
 
-use crate::{BrpStreamMessage, StreamClientId, StreamMessage, StreamMessageKind, StreamSender};
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+use crate::{
+    bind_tcp,
+    encoding::{Compression, Encoding, COMPRESSION_QUERY_KEY, ENCODING_QUERY_KEY},
+    error_codes,
+    record::{ConnectionRecorder, StreamRecorder},
+    subscription::{Subscriptions, TaggedResponse},
+    ServerAddress, StreamClient, StreamSender, StreamServerEvent, ACCEPT_RETRY_DELAY,
+    MAX_REQUEST_SIZE,
+};
 
 /// The default port that the WebSocket server will listen on.
 pub const DEFAULT_PORT: u16 = 3000;
 
 /// The default host address that WebSocket server will use.
 pub const DEFAULT_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+
+/// The default interval between two pings sent to a client.
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The default time without any message from a client before it is disconnected.
+pub const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// The origins allowed to connect by default: pages served from this machine and the hosted inspector.
+/// An origin without a port allows every port.
+pub const DEFAULT_ALLOWED_ORIGINS: &[&str] = &[
+    "http://localhost",
+    "https://localhost",
+    "http://127.0.0.1",
+    "https://127.0.0.1",
+    "http://[::1]",
+    "https://[::1]",
+    "https://bevy-remote-inspector.pages.dev",
+];
+
+/// How long the app waits on exit for clients to receive their last responses and a close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
 /// Add this plugin to your [`App`] to allow remote connections to inspect and modify entities.
 ///
 /// The defaults are:
@@ -47,6 +89,40 @@ pub struct RemoteStreamWebSocketPlugin {
 
     /// The port that the WebSocket server will listen on.
     port: u16,
+
+    /// The token clients must send to connect.
+    token: Option<String>,
+
+    /// Whether to log the URL to connect to, including the token, once the server is listening.
+    log_connect_url: bool,
+
+    /// The deflate level used for clients that ask for `compression=deflate`.
+    compression_level: Option<u32>,
+
+    /// Ping clients and disconnect the ones that stop answering.
+    heartbeat: Option<Heartbeat>,
+
+    /// How many of the following ports to try when `port` is already in use.
+    port_fallback: u16,
+
+    /// Whether the server starts with the app, otherwise it waits for [`WebSocketServer::start`].
+    autostart: bool,
+
+    /// The maximum number of connected clients.
+    max_clients: Option<usize>,
+
+    /// Decides whether a client can open its stream.
+    admission: Option<AdmissionHook>,
+
+    /// The web pages allowed to connect, `*` allows every page.
+    allowed_origins: Vec<String>,
+
+    /// The file every response sent to clients is recorded to.
+    recording: Option<PathBuf>,
+
+    /// Serve `wss://` with this certificate.
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
 }
 
 impl RemoteStreamWebSocketPlugin {
@@ -63,6 +139,133 @@ impl RemoteStreamWebSocketPlugin {
         self.port = port;
         self
     }
+
+    /// When the port is already in use, try up to `attempts` following ports instead of failing.
+    /// The port actually used is reported with [`StreamServerEvent::Listening`].
+    #[must_use]
+    pub fn with_port_fallback(mut self, attempts: u16) -> Self {
+        self.port_fallback = attempts;
+        self
+    }
+
+    /// Set whether the server starts with the app. When `false`, start it later with
+    /// [`WebSocketServer::start`].
+    #[must_use]
+    pub fn with_autostart(mut self, autostart: bool) -> Self {
+        self.autostart = autostart;
+        self
+    }
+
+    /// Allow at most `max_clients` connected clients. Other clients are refused with
+    /// [`error_codes::SERVER_FULL`], unless the admission hook queues them.
+    /// Each `stream/subscribe` of a connection counts as another client, and is refused
+    /// instead of queued when the server is full.
+    #[must_use]
+    pub fn with_max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = Some(max_clients);
+        self
+    }
+
+    /// Decide for each client whether it can open its stream, based on its origin,
+    /// method and params. Runs on the IO task pool once the client sent its request,
+    /// and again for each `stream/subscribe` of the connection.
+    #[must_use]
+    pub fn with_admission(
+        mut self,
+        admission: impl Fn(&AdmissionRequest) -> Admission + Send + Sync + 'static,
+    ) -> Self {
+        self.admission = Some(Arc::new(admission));
+        self
+    }
+
+    /// Only allow web pages from `origins` to connect, e.g. `https://example.com` or
+    /// `http://localhost:8080`. An origin without a port allows every port, `*` allows every page.
+    ///
+    /// Requests from other pages are rejected with `403`. Clients that are not browsers, and
+    /// so send no `Origin` header, are not affected. Defaults to [`DEFAULT_ALLOWED_ORIGINS`].
+    #[must_use]
+    pub fn with_allowed_origins(
+        mut self,
+        origins: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.allowed_origins = origins.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Also allow web pages from `origin` to connect, see [`Self::with_allowed_origins`].
+    #[must_use]
+    pub fn with_allowed_origin(mut self, origin: impl Into<String>) -> Self {
+        self.allowed_origins.push(origin.into());
+        self
+    }
+
+    /// Record every response sent to clients to a JSONL file at `path`, e.g. to attach a session
+    /// to a bug report. Use [`StreamReplay`](crate::record::StreamReplay) to serve it back.
+    #[must_use]
+    pub fn with_recording(mut self, path: impl Into<PathBuf>) -> Self {
+        self.recording = Some(path.into());
+        self
+    }
+
+    /// Require clients to send `token` to connect, either in the `token` query param
+    /// or as an `Authorization: Bearer` header. Other connections are rejected with `401`.
+    #[must_use]
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Like [`Self::with_token`], with a random token generated for this session.
+    /// The URL to connect to is logged at startup.
+    #[must_use]
+    pub fn with_generated_token(mut self) -> Self {
+        self.token = Some(generate_token());
+        self.log_connect_url = true;
+        self
+    }
+
+    /// Set whether to log the URL to connect to, including the token, once the server is listening.
+    #[must_use]
+    pub fn with_connect_url_logged(mut self, log_connect_url: bool) -> Self {
+        self.log_connect_url = log_connect_url;
+        self
+    }
+
+    /// Send a ping to every client each `interval`, and disconnect clients that send nothing,
+    /// not even a pong, for `timeout`. Their streams are closed as if they disconnected.
+    #[must_use]
+    pub fn with_heartbeat(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.heartbeat = Some(Heartbeat { interval, timeout });
+        self
+    }
+
+    /// Never ping clients, they are only disconnected when their connection closes.
+    #[must_use]
+    pub fn without_heartbeat(mut self) -> Self {
+        self.heartbeat = None;
+        self
+    }
+
+    /// Let clients ask for their messages to be deflate compressed with `compression=deflate`,
+    /// using `level` from 0 (none) to 9 (best).
+    ///
+    /// Messages are compressed one by one and sent as binary frames, this is not the
+    /// `permessage-deflate` extension which tungstenite does not support.
+    #[cfg(feature = "deflate")]
+    #[must_use]
+    pub fn with_compression(mut self, level: u32) -> Self {
+        self.compression_level = Some(level.min(9));
+        self
+    }
+
+    /// Serve `wss://` instead of `ws://`, required by browsers to connect to a non-localhost host
+    /// from a page served over HTTPS.
+    #[cfg(feature = "tls")]
+    #[must_use]
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
 }
 
 impl Default for RemoteStreamWebSocketPlugin {
@@ -70,6 +273,24 @@ impl Default for RemoteStreamWebSocketPlugin {
         Self {
             address: DEFAULT_ADDR,
             port: DEFAULT_PORT,
+            token: None,
+            log_connect_url: false,
+            compression_level: None,
+            heartbeat: Some(Heartbeat {
+                interval: DEFAULT_HEARTBEAT_INTERVAL,
+                timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            }),
+            port_fallback: 0,
+            autostart: true,
+            max_clients: None,
+            admission: None,
+            allowed_origins: DEFAULT_ALLOWED_ORIGINS
+                .iter()
+                .map(|origin| origin.to_string())
+                .collect(),
+            recording: None,
+            #[cfg(feature = "tls")]
+            tls: None,
         }
     }
 }
@@ -78,7 +299,125 @@ impl Plugin for RemoteStreamWebSocketPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(HostAddress(self.address))
             .insert_resource(HostPort(self.port))
-            .add_systems(Startup, start_server);
+            .insert_resource(ServerOptions {
+                token: self.token.clone(),
+                log_connect_url: self.log_connect_url,
+                compression_level: self.compression_level,
+                heartbeat: self.heartbeat,
+                port_fallback: self.port_fallback,
+                client_slots: self.max_clients.map(|max| Arc::new(Semaphore::new(max))),
+                admission: self.admission.clone(),
+                allowed_origins: self.allowed_origins.clone(),
+                recorder: self.recording.as_ref().and_then(|path| {
+                    StreamRecorder::create(path)
+                        .inspect_err(|err| {
+                            error!("Failed to record stream responses to {path:?}: {err}")
+                        })
+                        .ok()
+                }),
+                #[cfg(feature = "tls")]
+                tls: self.tls.clone(),
+            })
+            .insert_resource(WebSocketServer {
+                enabled: self.autostart,
+                restart: false,
+                running: None,
+                stopping: None,
+            })
+            .add_systems(
+                Update,
+                update_server.run_if(resource_changed::<WebSocketServer>),
+            )
+            .add_systems(
+                Last,
+                close_on_exit
+                    .after(crate::on_app_exit)
+                    .run_if(on_event::<AppExit>),
+            );
+    }
+}
+
+/// Controls the WebSocket server at runtime, e.g. from a debug menu.
+///
+/// Changes are applied in [`Update`]. Stopping the server closes the listener and disconnects
+/// every client, running the `on_disconnect` handlers of their streams.
+#[derive(Resource)]
+pub struct WebSocketServer {
+    enabled: bool,
+    restart: bool,
+    running: Option<RunningServer>,
+    /// [`RunningServer::finished`] of the last stopped server, the next one waits for it
+    /// so the port is free again.
+    stopping: Option<Receiver<()>>,
+}
+
+struct RunningServer {
+    /// Dropped to stop the server.
+    shutdown: Sender<()>,
+    /// Closed once the server and all its connections are done.
+    finished: Receiver<()>,
+}
+
+impl WebSocketServer {
+    /// Start the server if it is stopped.
+    pub fn start(&mut self) {
+        self.enabled = true;
+    }
+
+    /// Stop the server and disconnect every client.
+    pub fn stop(&mut self) {
+        self.enabled = false;
+    }
+
+    /// Stop the server and start it again with the current [`HostAddress`] and [`HostPort`],
+    /// e.g. after changing them.
+    pub fn restart(&mut self) {
+        self.enabled = true;
+        self.restart = true;
+    }
+
+    /// Whether the server was started. Use [`StreamServerEvent`] to know if it could bind its port.
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+}
+
+/// A client asking to open a stream, see [`RemoteStreamWebSocketPlugin::with_admission`].
+pub struct AdmissionRequest<'a> {
+    /// The `Origin` header of the connection, set by browsers.
+    pub origin: Option<&'a str>,
+    pub method: &'a str,
+    pub params: Option<&'a Value>,
+}
+
+/// Whether a client can open its stream.
+pub enum Admission {
+    /// Open the stream if there is room for another client, refuse it otherwise.
+    Accept,
+    /// Open the stream once there is room for another client.
+    Queue,
+    /// Refuse the client with this error.
+    Reject(BrpError),
+}
+
+type AdmissionHook = Arc<dyn Fn(&AdmissionRequest) -> Admission + Send + Sync>;
+
+/// Held by the server and each of its connections, tells them when the server is stopped.
+#[derive(Clone)]
+struct Shutdown {
+    signal: Receiver<()>,
+    /// Keeps [`RunningServer::finished`] open while this connection is alive.
+    _running: Sender<()>,
+}
+
+impl Shutdown {
+    async fn wait(&self) {
+        // Nothing is ever sent, this returns when the sender is dropped
+        let _ = self.signal.recv().await;
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.signal.is_closed()
     }
 }
 
@@ -88,50 +427,274 @@ pub struct HostAddress(pub IpAddr);
 #[derive(Debug, Resource, Reflect)]
 pub struct HostPort(pub u16);
 
-fn start_server(sender: Res<StreamSender>, address: Res<HostAddress>, remote_port: Res<HostPort>) {
+/// Settings shared by every connection of the server.
+#[derive(Resource, Clone)]
+struct ServerOptions {
+    token: Option<String>,
+    log_connect_url: bool,
+    compression_level: Option<u32>,
+    heartbeat: Option<Heartbeat>,
+    port_fallback: u16,
+    /// One permit per client that can connect.
+    client_slots: Option<Arc<Semaphore>>,
+    admission: Option<AdmissionHook>,
+    allowed_origins: Vec<String>,
+    recorder: Option<StreamRecorder>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Heartbeat {
+    interval: Duration,
+    timeout: Duration,
+}
+
+const TOKEN_QUERY_KEY: &str = "token";
+
+impl ServerOptions {
+    fn scheme(&self) -> &'static str {
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() {
+            return "wss";
+        }
+
+        "ws"
+    }
+
+    /// Requests without an `Origin` header don't come from a web page and are always allowed.
+    fn is_origin_allowed(&self, origin: &HeaderValue) -> bool {
+        is_origin_allowed(&self.allowed_origins, origin)
+    }
+
+    fn is_authorized<B>(&self, request: &Request<B>) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+
+        let from_query =
+            query_param(request, TOKEN_QUERY_KEY).and_then(|token| urlencoding::decode(token).ok());
+        let from_header = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(Cow::Borrowed);
+
+        from_query
+            .or(from_header)
+            .is_some_and(|provided| constant_time_eq(provided.as_bytes(), token.as_bytes()))
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Whether `origin` is in `allowed_origins`, on any port.
+pub(crate) fn is_origin_allowed(allowed_origins: &[String], origin: &HeaderValue) -> bool {
+    if origin.is_empty() || allowed_origins.iter().any(|allowed| allowed == "*") {
+        return true;
+    }
+
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+
+    allowed_origins.iter().any(|allowed| {
+        origin == allowed
+            || origin
+                .strip_prefix(allowed.as_str())
+                .and_then(|rest| rest.strip_prefix(':'))
+                .is_some_and(|port| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()))
+    })
+}
+
+/// A random 128-bit token, hex encoded, e.g. to authenticate clients or identify sessions.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("Failed to generate a random token");
+
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn update_server(
+    mut server: ResMut<WebSocketServer>,
+    sender: Res<StreamSender>,
+    address: Res<HostAddress>,
+    remote_port: Res<HostPort>,
+    options: Res<ServerOptions>,
+) {
+    // Don't trigger this system again for our own changes
+    let server = server.bypass_change_detection();
+
+    if server.restart || !server.enabled {
+        server.restart = false;
+
+        if let Some(RunningServer { shutdown, finished }) = server.running.take() {
+            drop(shutdown);
+            server.stopping = Some(finished);
+        }
+    }
+
+    if !server.enabled || server.running.is_some() {
+        return;
+    }
+
+    let (shutdown, signal) = channel::bounded(1);
+    let (running, finished) = channel::bounded(1);
+    server.running = Some(RunningServer { shutdown, finished });
+
+    let stopping = server.stopping.take();
+    let address = address.0;
+    let port = remote_port.0;
+    let sender = sender.clone();
+    let options = Arc::new(options.clone());
+
     IoTaskPool::get()
-        .spawn(server_main(address.0, remote_port.0, sender.clone()))
+        .spawn(async move {
+            // The previous server may still hold the port
+            if let Some(stopping) = stopping {
+                wait_finished(&stopping).await;
+            }
+
+            server_main(
+                address,
+                port,
+                sender,
+                options,
+                Shutdown {
+                    signal,
+                    _running: running,
+                },
+            )
+            .await
+        })
         .detach();
 }
 
-struct TcpClient {
-    id: StreamClientId,
-    stream: Async<TcpStream>,
+/// Wait for a stopped server to close its listener and connections, at most [`CLOSE_TIMEOUT`].
+async fn wait_finished(finished: &Receiver<()>) {
+    smol::future::or(
+        async {
+            // Nothing is ever sent, this returns once the server and its connections are dropped
+            let _ = finished.recv().await;
+        },
+        async {
+            Timer::after(CLOSE_TIMEOUT).await;
+        },
+    )
+    .await;
+}
+
+/// Stop the server and give clients a moment to receive their last responses and a close frame.
+fn close_on_exit(mut server: ResMut<WebSocketServer>) {
+    let Some(RunningServer { shutdown, finished }) = server.running.take() else {
+        return;
+    };
+
+    drop(shutdown);
+    smol::block_on(wait_finished(&finished));
 }
 
 async fn server_main(
     address: IpAddr,
     port: u16,
-    request_sender: Sender<StreamMessage>,
+    request_sender: StreamSender,
+    options: Arc<ServerOptions>,
+    shutdown: Shutdown,
 ) -> anyhow::Result<()> {
-    let listener = Async::<TcpListener>::bind((address, port))?;
-    let mut client_id: usize = 0;
+    let listener = match bind_tcp(address, port, options.port_fallback) {
+        Ok(listener) => listener,
+        Err(error) => {
+            request_sender.report(StreamServerEvent::BindFailed {
+                address: SocketAddr::new(address, port).into(),
+                error: error.to_string(),
+            });
+            return Err(error.into());
+        }
+    };
+    let address = listener.get_ref().local_addr()?;
+    request_sender.report(StreamServerEvent::Listening(address.into()));
+
+    if options.log_connect_url {
+        let scheme = options.scheme();
+        match &options.token {
+            Some(token) => {
+                info!("Stream server listening on {scheme}://{address}/?{TOKEN_QUERY_KEY}={token}")
+            }
+            None => info!("Stream server listening on {scheme}://{address}"),
+        }
+    }
+
     loop {
-        let (stream, _) = listener.accept().await?;
-        client_id = client_id.wrapping_add(1);
-        let client = TcpClient {
-            id: StreamClientId(client_id),
-            stream,
+        let accepted = smol::future::or(async { Some(listener.accept().await) }, async {
+            shutdown.wait().await;
+            None
+        })
+        .await;
+
+        let stream = match accepted {
+            Some(Ok((stream, _))) => stream,
+            Some(Err(error)) => {
+                request_sender.report(StreamServerEvent::AcceptFailed {
+                    address: ServerAddress::Socket(address),
+                    error: error.to_string(),
+                });
+                Timer::after(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+            None => break,
         };
         let request_sender = request_sender.clone();
+        let options = options.clone();
+        let shutdown = shutdown.clone();
         IoTaskPool::get()
             .spawn(async move {
-                let _ = handle_client(client, request_sender).await;
+                let connection = accept_client(stream, request_sender, options, shutdown.clone());
+                let _ = smol::future::or(connection, async {
+                    shutdown.wait().await;
+                    Ok(())
+                })
+                .await;
             })
             .detach();
     }
+
+    request_sender.report(StreamServerEvent::Stopped(address.into()));
+
+    Ok(())
+}
+
+async fn accept_client(
+    stream: Async<TcpStream>,
+    request_sender: StreamSender,
+    options: Arc<ServerOptions>,
+    shutdown: Shutdown,
+) -> anyhow::Result<()> {
+    #[cfg(feature = "tls")]
+    if let Some(tls) = &options.tls {
+        let stream = tls.acceptor().accept(stream).await?;
+        return handle_client(stream, request_sender, options, shutdown).await;
+    }
+
+    handle_client(stream, request_sender, options, shutdown).await
 }
 
 async fn handle_client(
-    client: TcpClient,
-    request_sender: Sender<StreamMessage>,
+    stream: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    request_sender: StreamSender,
+    options: Arc<ServerOptions>,
+    shutdown: Shutdown,
 ) -> anyhow::Result<()> {
     http1::Builder::new()
         .keep_alive(true)
         .timer(SmolTimer::new())
         .serve_connection(
-            FuturesIo::new(client.stream),
-            service::service_fn(|request| process_request(request, &request_sender, client.id)),
+            FuturesIo::new(stream),
+            service::service_fn(|request| {
+                process_request(request, &request_sender, &options, &shutdown)
+            }),
         )
         .with_upgrades()
         .await?;
@@ -141,12 +704,28 @@ async fn handle_client(
 
 async fn process_request(
     mut request: Request<Incoming>,
-    request_sender: &Sender<StreamMessage>,
-    client_id: StreamClientId,
+    request_sender: &StreamSender,
+    options: &Arc<ServerOptions>,
+    shutdown: &Shutdown,
 ) -> anyhow::Result<Response<Full<Bytes>>> {
     let default_origin = HeaderValue::from_static("");
     let origin = request.headers().get(ORIGIN).unwrap_or(&default_origin);
 
+    if !options.is_origin_allowed(origin) {
+        let response_body = serde_json::to_string(&BrpError {
+            code: error_codes::ORIGIN_NOT_ALLOWED,
+            message: "Origin is not allowed".into(),
+            data: None,
+        })?;
+
+        // No `Access-Control-Allow-Origin`, the page can't read the response
+        let response = Response::builder()
+            .status(403)
+            .body(Full::new(response_body.into_bytes().into()))?;
+
+        return Ok(response);
+    }
+
     if request.method() == Method::OPTIONS {
         let response = Response::builder()
             .status(200)
@@ -158,11 +737,56 @@ async fn process_request(
         return Ok(response);
     }
 
+    if request.method() == Method::POST {
+        if !options.is_authorized(&request) {
+            return unauthorized(origin);
+        }
+
+        let origin = origin.clone();
+        let response = match read_request(request).await {
+            Ok(request) => request_sender.client().request(request).await?,
+            Err(err) if err.is::<LengthLimitError>() => return payload_too_large(&origin),
+            Err(err) => BrpResponse::new(
+                None,
+                Err(BrpError {
+                    code: error_codes::INVALID_REQUEST,
+                    message: format!("{err}"),
+                    data: None,
+                }),
+            ),
+        };
+
+        let response = Response::builder()
+            .status(200)
+            .header(ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::new(serde_json::to_vec(&response)?.into()))?;
+
+        return Ok(response);
+    }
+
     if hyper_tungstenite::is_upgrade_request(&request) {
+        if !options.is_authorized(&request) {
+            return unauthorized(origin);
+        }
+
+        let client_origin = origin
+            .to_str()
+            .ok()
+            .filter(|origin| !origin.is_empty())
+            .map(String::from);
         let (response, websocket) = hyper_tungstenite::upgrade(&mut request, None)?;
 
-        let body = match validate_websocket_request(&request) {
-            Ok(body) => body,
+        let validated = validate_websocket_request(&request).and_then(|body| {
+            Ok((
+                body,
+                websocket_encoding(&request)?,
+                websocket_compression(&request, options)?,
+            ))
+        });
+
+        let (body, encoding, compression) = match validated {
+            Ok(validated) => validated,
             Err(err) => {
                 let response = serde_json::to_string(&BrpError {
                     code: error_codes::INVALID_REQUEST,
@@ -174,12 +798,20 @@ async fn process_request(
             }
         };
 
+        let handshake = Handshake {
+            request: body,
+            encoding,
+            compression,
+            origin: client_origin,
+        };
+
         IoTaskPool::get()
             .spawn(process_websocket_stream(
                 websocket,
                 request_sender.clone(),
-                body,
-                client_id,
+                handshake,
+                options.clone(),
+                shutdown.clone(),
             ))
             .detach();
 
@@ -201,12 +833,78 @@ async fn process_request(
     return Ok(response);
 }
 
+fn unauthorized(origin: &HeaderValue) -> anyhow::Result<Response<Full<Bytes>>> {
+    let response_body = serde_json::to_string(&BrpError {
+        code: error_codes::UNAUTHORIZED,
+        message: "Missing or invalid token".into(),
+        data: None,
+    })?;
+
+    let response = Response::builder()
+        .status(401)
+        .header(ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+        .body(Full::new(response_body.into_bytes().into()))?;
+
+    Ok(response)
+}
+
+fn payload_too_large(origin: &HeaderValue) -> anyhow::Result<Response<Full<Bytes>>> {
+    let response_body = serde_json::to_string(&BrpError {
+        code: error_codes::INVALID_REQUEST,
+        message: format!("Request body is larger than {MAX_REQUEST_SIZE} bytes"),
+        data: None,
+    })?;
+
+    let response = Response::builder()
+        .status(413)
+        .header(ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+        .body(Full::new(response_body.into_bytes().into()))?;
+
+    Ok(response)
+}
+
+/// Read a one-shot JSON-RPC request from the body of a `POST`.
+async fn read_request(request: Request<Incoming>) -> anyhow::Result<BrpRequest> {
+    let body = Limited::new(request.into_body(), MAX_REQUEST_SIZE)
+        .collect()
+        .await
+        .map_err(|err| match err.downcast::<LengthLimitError>() {
+            Ok(err) => anyhow::Error::from(*err),
+            Err(err) => anyhow::anyhow!(err),
+        })?
+        .to_bytes();
+    let request = serde_json::from_slice::<BrpRequest>(&body)?;
+
+    if request.jsonrpc != "2.0" {
+        anyhow::bail!("JSON-RPC request requires `\"jsonrpc\": \"2.0\"`")
+    }
+
+    Ok(request)
+}
+
+/// What a client sent when upgrading its connection.
+struct Handshake {
+    /// `None` when the client sends it as its first message.
+    request: Option<BrpRequest>,
+    encoding: Encoding,
+    compression: Compression,
+    origin: Option<String>,
+}
+
 async fn process_websocket_stream(
     ws: HyperWebsocket,
-    request_sender: Sender<StreamMessage>,
-    request: BrpRequest,
-    client_id: StreamClientId,
+    request_sender: StreamSender,
+    handshake: Handshake,
+    options: Arc<ServerOptions>,
+    shutdown: Shutdown,
 ) -> anyhow::Result<()> {
+    let Handshake {
+        request,
+        encoding,
+        compression,
+        origin,
+    } = handshake;
+    let heartbeat = options.heartbeat;
     let ws = ws.await?;
 
     let (write_stream, read_stream) = ws.split();
@@ -214,43 +912,194 @@ async fn process_websocket_stream(
     let (result_sender, result_receiver) = channel::bounded(32);
 
     IoTaskPool::get()
-        .spawn(send_stream_response(write_stream, result_receiver))
+        .spawn(send_stream_response(
+            write_stream,
+            result_receiver,
+            encoding,
+            compression,
+            heartbeat.map(|heartbeat| heartbeat.interval),
+            options.recorder.as_ref().map(StreamRecorder::connection),
+            shutdown.clone(),
+        ))
         .detach();
 
-    send_stream_message(
-        read_stream,
-        request_sender.clone(),
-        request,
-        result_sender,
-        client_id,
-    )
-    .await?;
+    let mut messages = ClientMessages {
+        stream: read_stream,
+        timeout: heartbeat.map(|heartbeat| heartbeat.timeout),
+        shutdown,
+    };
+    let request = match request {
+        Some(request) => request,
+        None => match read_handshake(&mut messages, encoding).await {
+            Ok(request) => request,
+            Err(err) => {
+                let error = BrpError {
+                    code: error_codes::INVALID_REQUEST,
+                    message: format!("{err}"),
+                    data: None,
+                };
+
+                return reply_error(&result_sender, None, error).await;
+            }
+        },
+    };
+
+    let gate = AdmissionGate {
+        options,
+        origin,
+        shutdown: messages.shutdown.clone(),
+    };
+
+    // Held until the client disconnects
+    let _slot = match gate.admit(&request, true).await {
+        Ok(slot) => slot,
+        Err(error) => return reply_error(&result_sender, request.id, error).await,
+    };
+
+    let client = request_sender.client();
+    let subscriptions = Subscriptions::new(request_sender, result_sender, gate);
+
+    send_stream_message(messages, client, request, subscriptions, encoding).await?;
 
     Ok(())
 }
 
-const QUERY_KEY: &str = "body";
-
-fn validate_websocket_request(request: &Request<Incoming>) -> anyhow::Result<BrpRequest> {
-    let body = request
-        .uri()
-        .query()
-        .and_then(|query| {
-            // Simple query string parsing
-            for pair in query.split('&') {
-                let mut it = pair.split('=').take(2);
-                match (it.next(), it.next()) {
-                    (Some(k), Some(v)) if k == QUERY_KEY => return Some(v),
-                    _ => {}
-                };
-            }
-            None
+async fn reply_error(
+    result_sender: &Sender<TaggedResponse>,
+    id: Option<Value>,
+    error: BrpError,
+) -> anyhow::Result<()> {
+    result_sender
+        .send(TaggedResponse {
+            response: BrpResponse::new(id, Err(error)),
+            subscription: None,
         })
-        .ok_or_else(|| anyhow::anyhow!("Missing body"))?;
+        .await?;
 
-    let body = urlencoding::decode(body)?.into_owned();
+    Ok(())
+}
+
+/// Admits the streams opened by a connection, the first one and each subscription.
+pub(crate) struct AdmissionGate {
+    options: Arc<ServerOptions>,
+    origin: Option<String>,
+    shutdown: Shutdown,
+}
 
-    match serde_json::from_str::<BrpRequest>(&body) {
+impl AdmissionGate {
+    /// Run the admission hook and take a client slot. With `allow_queue`, queued clients wait
+    /// for a slot, otherwise they are refused when the server is full.
+    pub(crate) async fn admit(
+        &self,
+        request: &BrpRequest,
+        allow_queue: bool,
+    ) -> Result<Option<SemaphoreGuardArc>, BrpError> {
+        admit(
+            &self.options,
+            request,
+            self.origin.as_deref(),
+            &self.shutdown,
+            allow_queue,
+        )
+        .await
+    }
+}
+
+async fn admit(
+    options: &ServerOptions,
+    request: &BrpRequest,
+    origin: Option<&str>,
+    shutdown: &Shutdown,
+    allow_queue: bool,
+) -> Result<Option<SemaphoreGuardArc>, BrpError> {
+    let admission = match &options.admission {
+        Some(admission) => admission(&AdmissionRequest {
+            origin,
+            method: &request.method,
+            params: request.params.as_ref(),
+        }),
+        None => Admission::Accept,
+    };
+
+    let queue = match admission {
+        Admission::Accept => false,
+        Admission::Queue => allow_queue,
+        Admission::Reject(error) => return Err(error),
+    };
+
+    let Some(slots) = &options.client_slots else {
+        return Ok(None);
+    };
+
+    if let Some(slot) = slots.try_acquire_arc() {
+        return Ok(Some(slot));
+    }
+
+    let server_full = || BrpError {
+        code: error_codes::SERVER_FULL,
+        message: "Too many clients are connected".into(),
+        data: None,
+    };
+
+    if !queue {
+        return Err(server_full());
+    }
+
+    smol::future::or(async { Ok(Some(slots.acquire_arc().await)) }, async {
+        shutdown.wait().await;
+        Err(server_full())
+    })
+    .await
+}
+
+pub(crate) const QUERY_KEY: &str = "body";
+
+pub(crate) fn query_param<'a, B>(request: &'a Request<B>, key: &str) -> Option<&'a str> {
+    let query = request.uri().query()?;
+
+    // Simple query string parsing
+    for pair in query.split('&') {
+        let mut it = pair.split('=').take(2);
+        match (it.next(), it.next()) {
+            (Some(k), Some(v)) if k == key => return Some(v),
+            _ => {}
+        };
+    }
+
+    None
+}
+
+/// Returns `None` when the request is not in the query string,
+/// the client then sends it as the first message instead.
+fn validate_websocket_request(request: &Request<Incoming>) -> anyhow::Result<Option<BrpRequest>> {
+    let Some(body) = query_param(request, QUERY_KEY) else {
+        return Ok(None);
+    };
+
+    let body = urlencoding::decode(body)?;
+
+    parse_request(serde_json::from_str(&body)?).map(Some)
+}
+
+fn websocket_encoding(request: &Request<Incoming>) -> anyhow::Result<Encoding> {
+    match query_param(request, ENCODING_QUERY_KEY) {
+        Some(name) => Encoding::from_name(name),
+        None => Ok(Encoding::default()),
+    }
+}
+
+fn websocket_compression(
+    request: &Request<Incoming>,
+    options: &ServerOptions,
+) -> anyhow::Result<Compression> {
+    match query_param(request, COMPRESSION_QUERY_KEY) {
+        Some(name) => Compression::from_name(name, options.compression_level),
+        None => Ok(Compression::default()),
+    }
+}
+
+fn parse_request(body: Value) -> anyhow::Result<BrpRequest> {
+    match serde_json::from_value::<BrpRequest>(body) {
         Ok(req) => {
             if req.jsonrpc != "2.0" {
                 anyhow::bail!("JSON-RPC request requires `\"jsonrpc\": \"2.0\"`")
@@ -262,59 +1111,315 @@ fn validate_websocket_request(request: &Request<Incoming>) -> anyhow::Result<Brp
     }
 }
 
+/// Wait for the first text message, which holds the request that opens the stream.
+async fn read_handshake(
+    messages: &mut ClientMessages,
+    encoding: Encoding,
+) -> anyhow::Result<BrpRequest> {
+    while let Some(message) = messages.next().await {
+        let message = message?;
+        if let Message::Close(_) = message {
+            break;
+        }
+
+        if let Some(body) = encoding.decode(&message) {
+            return parse_request(body?);
+        }
+    }
+
+    anyhow::bail!("Connection closed before the request was sent")
+}
+
 async fn send_stream_message(
-    mut stream: SplitStream<HyperWebsocketStream>,
-    sender: Sender<StreamMessage>,
+    mut messages: ClientMessages,
+    mut client: StreamClient,
     request: BrpRequest,
-    result_sender: Sender<BrpResponse>,
-    client_id: StreamClientId,
+    mut subscriptions: Subscriptions,
+    encoding: Encoding,
 ) -> anyhow::Result<()> {
-    let _ = sender
-        .send(StreamMessage {
-            client_id,
-            kind: StreamMessageKind::Connect(
-                request.id,
-                BrpStreamMessage {
-                    method: request.method,
-                    params: request.params,
-                    sender: result_sender,
-                },
-            ),
+    subscriptions.connect(&mut client, request).await?;
+    loop {
+        let message = smol::future::or(async { Some(messages.next().await) }, async {
+            subscriptions.remove_ended().await;
+            None
         })
-        .await?;
-    while let Some(message) = stream.next().await {
-        match message {
-            Ok(Message::Text(text)) => {
-                let msg = serde_json::from_str::<Value>(&text)?;
-                let _ = sender
-                    .send(StreamMessage {
-                        client_id,
-                        kind: StreamMessageKind::Data(msg),
-                    })
-                    .await?;
+        .await;
+
+        let message = match message {
+            // A subscription ended, wait for the next message
+            None => continue,
+            Some(None) | Some(Some(Ok(Message::Close(_)) | Err(_))) => break,
+            Some(Some(Ok(message))) => message,
+        };
+
+        let msg = match encoding.decode(&message) {
+            Some(Ok(msg)) => msg,
+            Some(Err(err)) => {
+                let error = BrpError {
+                    code: error_codes::PARSE_ERROR,
+                    message: format!("Failed to parse request: {err}"),
+                    data: None,
+                };
+                subscriptions.reply(None, Err(error)).await?;
+                continue;
             }
-            Ok(Message::Close(_)) | Err(_) => return Ok(()),
-            _ => {}
+            None => continue,
+        };
+
+        if let Some(msg) = subscriptions.handle(msg).await? {
+            client.send(msg).await?;
         }
     }
-    let _ = sender
-        .send(StreamMessage {
-            client_id,
-            kind: StreamMessageKind::Disconnect,
-        })
-        .await?;
+    subscriptions.close().await?;
+    client.disconnect().await?;
 
     Ok(())
 }
 
 async fn send_stream_response(
     mut stream: SplitSink<HyperWebsocketStream, Message>,
-    result_receiver: Receiver<BrpResponse>,
+    result_receiver: Receiver<TaggedResponse>,
+    encoding: Encoding,
+    compression: Compression,
+    ping_interval: Option<Duration>,
+    recorder: Option<ConnectionRecorder>,
+    shutdown: Shutdown,
 ) -> anyhow::Result<()> {
-    while let Ok(response) = result_receiver.recv().await {
-        let response = serde_json::to_string(&response)?;
-        stream.send(Message::text(response)).await?;
+    let mut ping = ping_interval.map_or_else(Timer::never, Timer::interval);
+
+    loop {
+        let outgoing = smol::future::or(
+            async { result_receiver.recv().await.ok().map(Outgoing::Response) },
+            async {
+                ping.next().await;
+                Some(Outgoing::Ping)
+            },
+        )
+        .await;
+
+        let message = match outgoing {
+            Some(Outgoing::Response(response)) => {
+                if let Some(recorder) = &recorder {
+                    recorder.record(&response);
+                }
+                compression.compress(encoding.encode(&response)?)?
+            }
+            Some(Outgoing::Ping) => Message::Ping(Vec::new()),
+            None => break,
+        };
+        stream.send(message).await?;
     }
 
+    // Every response was sent, tell the client why the connection ends
+    let close = if shutdown.is_stopped() {
+        CloseFrame {
+            code: CloseCode::Away,
+            reason: "Server shutting down".into(),
+        }
+    } else {
+        CloseFrame {
+            code: CloseCode::Normal,
+            reason: "Stream closed".into(),
+        }
+    };
+    let _ = stream.send(Message::Close(Some(close))).await;
+
     Ok(())
 }
+
+enum Outgoing {
+    Response(TaggedResponse),
+    Ping,
+}
+
+/// The messages sent by a client.
+struct ClientMessages {
+    stream: SplitStream<HyperWebsocketStream>,
+    /// Nothing received for this long means the client is gone.
+    timeout: Option<Duration>,
+    shutdown: Shutdown,
+}
+
+impl ClientMessages {
+    /// Wait for the next message of the client. Returns `None` when the connection is closed,
+    /// nothing was received within `timeout` or the server is stopped.
+    async fn next(&mut self) -> Option<Result<Message, tungstenite::Error>> {
+        let Self {
+            stream,
+            timeout,
+            shutdown,
+        } = self;
+
+        let message = async {
+            let Some(timeout) = *timeout else {
+                return stream.next().await;
+            };
+
+            smol::future::or(stream.next(), async {
+                Timer::after(timeout).await;
+                debug!("Client did not answer the heartbeat, disconnecting");
+                None
+            })
+            .await
+        };
+
+        smol::future::or(message, async {
+            shutdown.wait().await;
+            None
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::RemoteStreamPlugin;
+
+    #[test]
+    fn test_is_authorized() {
+        let mut app = App::new();
+        app.add_plugins(RemoteStreamWebSocketPlugin::default().with_token("s3cr3t"));
+        let options = app.world().resource::<ServerOptions>();
+
+        let request = |uri: &str, authorization: Option<&str>| {
+            let mut request = Request::builder().uri(uri);
+            if let Some(authorization) = authorization {
+                request = request.header(AUTHORIZATION, authorization);
+            }
+            request.body(()).unwrap()
+        };
+
+        let cases = [
+            ("/?token=s3cr3t", None, true),
+            ("/?body=%7B%7D&token=s3cr3t", None, true),
+            ("/", Some("Bearer s3cr3t"), true),
+            ("/?token=s3cr3x", None, false),
+            ("/", Some("Bearer s3cr3x"), false),
+            ("/?token=s3cr3", None, false),
+            ("/?token=s3cr3tt", None, false),
+            ("/", Some("Bearer s3cr3"), false),
+            ("/", Some("s3cr3t"), false),
+            ("/", Some("Basic s3cr3t"), false),
+            ("/", None, false),
+            ("/?token=", None, false),
+        ];
+
+        for (uri, authorization, expected) in cases {
+            assert_eq!(
+                options.is_authorized(&request(uri, authorization)),
+                expected,
+                "{uri} {authorization:?}"
+            );
+        }
+
+        // Without a token every request is authorized
+        let mut app = App::new();
+        app.add_plugins(RemoteStreamWebSocketPlugin::default());
+        let options = app.world().resource::<ServerOptions>();
+        assert!(options.is_authorized(&request("/", None)));
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"tokens"));
+        assert!(!constant_time_eq(b"token", b""));
+    }
+
+    #[test]
+    fn test_is_origin_allowed() {
+        let defaults: Vec<String> = DEFAULT_ALLOWED_ORIGINS
+            .iter()
+            .map(|origin| origin.to_string())
+            .collect();
+        let any = vec!["*".to_string()];
+
+        let cases: &[(&[String], &[u8], bool)] = &[
+            (&defaults, b"http://localhost:8080", true),
+            (&defaults, b"http://localhost", true),
+            (&defaults, b"https://127.0.0.1:3000", true),
+            (&defaults, b"", true),
+            (&defaults, b"http://localhost.evil.com", false),
+            (&defaults, b"http://localhost:", false),
+            (&defaults, b"http://localhost:80abc", false),
+            (&defaults, b"http://evil.com", false),
+            (&defaults, "http://localhost:80\u{e9}".as_bytes(), false),
+            (&any, b"http://evil.com", true),
+            (&any, "http://\u{e9}vil.com".as_bytes(), true),
+        ];
+
+        for (allowed_origins, origin, expected) in cases {
+            let header = HeaderValue::from_bytes(origin).unwrap();
+            assert_eq!(
+                is_origin_allowed(allowed_origins, &header),
+                *expected,
+                "{:?}",
+                String::from_utf8_lossy(origin)
+            );
+        }
+    }
+
+    #[derive(Resource, Default)]
+    struct ServerEvents(Vec<StreamServerEvent>);
+
+    fn collect_events(
+        mut reader: EventReader<StreamServerEvent>,
+        mut events: ResMut<ServerEvents>,
+    ) {
+        events.0.extend(reader.read().cloned());
+    }
+
+    /// Update the app until it received `count` server events.
+    fn wait_events(app: &mut App, count: usize) -> Vec<StreamServerEvent> {
+        let started_at = std::time::Instant::now();
+        while app.world().resource::<ServerEvents>().0.len() < count {
+            assert!(
+                started_at.elapsed() < Duration::from_secs(5),
+                "Timed out waiting for server events"
+            );
+            app.update();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        app.world().resource::<ServerEvents>().0.clone()
+    }
+
+    #[test]
+    fn test_restart_on_same_port() {
+        let port = TcpListener::bind((DEFAULT_ADDR, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let address = ServerAddress::from(SocketAddr::new(DEFAULT_ADDR, port));
+
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            RemoteStreamPlugin::default(),
+            RemoteStreamWebSocketPlugin::default().with_port(port),
+        ))
+        .init_resource::<ServerEvents>()
+        .add_systems(Update, collect_events);
+
+        assert_eq!(
+            wait_events(&mut app, 1),
+            vec![StreamServerEvent::Listening(address.clone())]
+        );
+
+        app.world_mut().resource_mut::<WebSocketServer>().restart();
+
+        assert_eq!(
+            wait_events(&mut app, 3)[1..],
+            [
+                StreamServerEvent::Stopped(address.clone()),
+                StreamServerEvent::Listening(address),
+            ]
+        );
+    }
+}
@@ -11,12 +11,17 @@ export function WebsocketConnector() {
   const setReadyState = useStore((state) => state.setReadyState);
   const { toast } = useToast();
   const { readyState, sendJsonMessage } = useWebSocket(shouldReconnect ? url : null, {
-    queryParams: {
-      body: JSON.stringify({
-        method: 'inspector/stream',
-        jsonrpc: '2.0',
-        id: WEB_SOCKET_MESSAGE_ID,
-      }),
+    onOpen: (event) => {
+      // The first message opens the stream, resuming the previous session if there is one
+      const session = useStore.getState().session;
+      (event.target as WebSocket).send(
+        JSON.stringify({
+          method: 'inspector/stream',
+          jsonrpc: '2.0',
+          id: WEB_SOCKET_MESSAGE_ID,
+          params: session ? { resume: session } : undefined,
+        }),
+      );
     },
     onError: (_e) => {
       const isManuallyConnect = useStore.getState().isManuallyConnect;
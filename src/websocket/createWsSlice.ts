@@ -23,6 +23,8 @@ export type WsSlice = {
   shouldReconnect: boolean;
   hasConnected: boolean;
   isManuallyConnect: boolean;
+  /** The stream session to resume after a reconnect, and the last batch received in it. */
+  session: { token: string; seq: number } | null;
 };
 
 let sendMessageInteral: SendJsonMessage = () => {
@@ -37,6 +39,7 @@ export const createWsSlice: StateCreator<SharedSlice, [], [], WsSlice> = (set, g
   shouldReconnect: true,
   isManuallyConnect: false,
   hasConnected: false,
+  session: null,
   sendMessage: ({
     callback,
     ...data
@@ -70,15 +73,10 @@ export const createWsSlice: StateCreator<SharedSlice, [], [], WsSlice> = (set, g
     set({ readyState });
 
     if (readyState === ReadyState.OPEN) {
+      // The state is kept until the server tells whether the session is resumed
       set({
         hasConnected: true,
         shouldReconnect: true,
-        childParentMap: new Map(),
-        entities: new Map(),
-        registry: new Map(),
-        componentNameToIdMap: new Map(),
-        components: new Map(),
-        inspectingEntity: null,
       });
       localStorage.setItem('ws_url', get().url!);
     }
@@ -110,8 +108,19 @@ export const createWsSlice: StateCreator<SharedSlice, [], [], WsSlice> = (set, g
         return;
       }
 
-      for (const item of event.result) {
-        if (item.kind === 'type_registry') {
+      const batch = toBatch(event.result);
+      const session = get().session;
+      if (session && batch.seq !== undefined) {
+        set({ session: { ...session, seq: batch.seq } });
+      }
+
+      for (const item of batch.events) {
+        if (item.kind === 'session') {
+          set({ session: { token: item.token, seq: batch.seq ?? 0 } });
+          if (!item.resumed) {
+            set({ ...emptyStreamState(), inspectingEntity: null });
+          }
+        } else if (item.kind === 'type_registry') {
           get().setRegistry(item.types);
         } else if (item.kind === 'component') {
           get().updateComponents(item.components);
@@ -119,6 +128,8 @@ export const createWsSlice: StateCreator<SharedSlice, [], [], WsSlice> = (set, g
           get().updateEntity(item.entity, item.mutation);
         } else if (item.kind === 'schedules') {
           get().setSchedules(item.schedules);
+        } else if (item.kind === 'resync') {
+          set(emptyStreamState());
         } else {
           console.log(item);
         }
@@ -129,10 +140,32 @@ export const createWsSlice: StateCreator<SharedSlice, [], [], WsSlice> = (set, g
   },
 });
 
+// Servers released before batches were introduced send a bare array of events, without `seq`
+// or session, so they can't be resumed.
+function toBatch(
+  result: StreamBatch | StreamEvent[],
+): Partial<StreamBatch> & Pick<StreamBatch, 'events'> {
+  return Array.isArray(result) ? { events: result } : result;
+}
+
+function emptyStreamState() {
+  return {
+    childParentMap: new Map(),
+    entities: new Map(),
+    registry: new Map(),
+    componentNameToIdMap: new Map(),
+    components: new Map(),
+  };
+}
+
 export function parseWsURL(input: string): string | undefined {
   try {
     const url = new URL(input);
-    const ws_url = `ws://${url.host}${url.pathname}`;
+    // Keep the access token, if the server requires one
+    const token = url.searchParams.get('token');
+    const search = token ? `?token=${encodeURIComponent(token)}` : '';
+    const protocol = url.protocol === 'wss:' || url.protocol === 'https:' ? 'wss' : 'ws';
+    const ws_url = `${protocol}://${url.host}${url.pathname}${search}`;
     return ws_url;
   } catch (e) {
     return;
@@ -141,14 +174,27 @@ export function parseWsURL(input: string): string | undefined {
 
 export type WsEvent = {
   id: string | null;
-  result: StreamEvent[];
+  result: StreamBatch | StreamEvent[];
   error?: {
     code: number;
     message: string;
   };
 };
 
-type StreamEvent = TypeRegistryEvent | ComponentsEvent | EntityEvent | ScheduleEvent;
+export type StreamBatch = {
+  seq: number;
+  frame: number;
+  tick: number;
+  events: StreamEvent[];
+};
+
+type StreamEvent =
+  | TypeRegistryEvent
+  | ComponentsEvent
+  | EntityEvent
+  | ScheduleEvent
+  | ResyncEvent
+  | SessionEvent;
 
 export type TypeRegistryEvent = {
   kind: 'type_registry';
@@ -171,6 +217,16 @@ export type ScheduleEvent = {
   schedules: ScheduleInfo[];
 };
 
+export type ResyncEvent = {
+  kind: 'resync';
+};
+
+export type SessionEvent = {
+  kind: 'session';
+  token: string;
+  resumed: boolean;
+};
+
 export type EntityMutaion = EntityMutationChange | EntityMutationRemove;
 
 export type EntityMutationChange = {